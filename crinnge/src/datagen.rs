@@ -0,0 +1,376 @@
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use crinnge_lib::{
+    board::Board,
+    moves::Move,
+    rng::Rng,
+    search::{info::SearchInfo, MIN_TB_WIN_SCORE},
+    thread_data::ThreadData,
+    timeman::TimeManager,
+    tt::TT,
+    types::*,
+};
+
+pub const USAGE: &str = "usage: crinnge datagen [threads N] [games N] [nodes N] [hash MB] \
+                         [plies N] [book FILE] out FILE";
+
+/// Openings scoring more than this after the random plies are too unbalanced to be useful
+const OPENING_MAX_SCORE: i32 = 1000;
+/// A game is adjudicated as a win once both sides agree on a score this large for a few plies
+const WIN_ADJ_SCORE: i32 = 2500;
+const WIN_ADJ_PLIES: i32 = 4;
+/// A game is adjudicated as a draw once the score stays this close to zero for a while
+const DRAW_ADJ_SCORE: i32 = 10;
+const DRAW_ADJ_PLIES: usize = 8;
+/// Draws are not adjudicated before this many plies after the opening
+const DRAW_ADJ_MIN_PLY: usize = 80;
+
+#[derive(Clone, Debug)]
+pub struct DatagenOptions {
+    pub threads: usize,
+    pub games: usize,
+    pub nodes: u64,
+    pub hash: usize,
+    /// Random plies played from the start position or book position
+    pub plies: usize,
+    pub book: Option<String>,
+    pub out: String,
+}
+
+impl DatagenOptions {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            threads: 1,
+            games: 1000,
+            nodes: 5000,
+            hash: 16,
+            plies: 8,
+            book: None,
+            out: String::new(),
+        };
+
+        while let Some(name) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {name}\n{USAGE}"))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid value for {name}: {value}"))
+            };
+            match name.as_str() {
+                "threads" => options.threads = number()?.max(1) as usize,
+                "games" => options.games = number()? as usize,
+                "nodes" => options.nodes = number()?.max(1),
+                "hash" => options.hash = number()?.max(1) as usize,
+                "plies" => options.plies = number()? as usize,
+                "book" => options.book = Some(value),
+                "out" => options.out = value,
+                _ => return Err(format!("Unknown datagen option {name}\n{USAGE}")),
+            }
+        }
+
+        if options.out.is_empty() {
+            return Err(format!("No output file given\n{USAGE}"));
+        }
+
+        Ok(options)
+    }
+}
+
+/// Counters shared between the datagen threads
+struct Progress {
+    games_started: AtomicUsize,
+    games_done: AtomicUsize,
+    positions: AtomicUsize,
+    start: Instant,
+}
+
+/// Play self-play games across threads, appending `fen | score | result` lines to the output file
+/// with the score and result from white's point of view
+pub fn run(options: &DatagenOptions) -> Result<(), Box<dyn Error>> {
+    let book = match &options.book {
+        Some(path) => load_book(path)?,
+        None => vec![],
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&options.out)?;
+    let writer = Mutex::new(BufWriter::new(file));
+    let progress = Progress {
+        games_started: AtomicUsize::new(0),
+        games_done: AtomicUsize::new(0),
+        positions: AtomicUsize::new(0),
+        start: Instant::now(),
+    };
+
+    println!(
+        "Generating {} games at {} nodes on {} threads into {}",
+        options.games, options.nodes, options.threads, options.out
+    );
+
+    let seed = Rng::from_time().next_u64();
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..options.threads)
+            .map(|id| {
+                let (book, writer, progress) = (&book, &writer, &progress);
+                let rng = Rng::new(seed ^ id as u64);
+                s.spawn(move || worker(options, book, rng, writer, progress))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .try_for_each(|h| h.join().expect("datagen thread panicked"))
+    })?;
+
+    writer.into_inner()?.flush()?;
+
+    Ok(())
+}
+
+/// Positions from a file of FENs or EPDs, one per line
+pub fn load_book(path: &str) -> Result<Vec<Board>, Box<dyn Error>> {
+    let mut book = vec![];
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        // EPD opcodes follow the position
+        let fen = line.split(';').next().unwrap_or_default().trim();
+        if fen.is_empty() {
+            continue;
+        }
+        let board =
+            Board::from_fen(fen).ok_or_else(|| format!("Invalid FEN on line {}: {fen}", i + 1))?;
+        book.push(board);
+    }
+
+    if book.is_empty() {
+        return Err(format!("No positions in book {path}").into());
+    }
+
+    Ok(book)
+}
+
+fn worker(
+    options: &DatagenOptions,
+    book: &[Board],
+    mut rng: Rng,
+    writer: &Mutex<BufWriter<File>>,
+    progress: &Progress,
+) -> std::io::Result<()> {
+    let mut tt = TT::new(options.hash);
+    let mut positions = vec![];
+
+    while progress.games_started.fetch_add(1, Ordering::Relaxed) < options.games {
+        tt.clear();
+        positions.clear();
+        match play_game(options, book, &mut rng, &tt, &mut positions) {
+            Some(result) => {
+                let mut writer = writer.lock().unwrap();
+                for (fen, score) in positions.iter() {
+                    writeln!(writer, "{fen} | {score} | {result:.1}")?;
+                }
+            }
+            // abandoned games have no result to label their positions with, but still count
+            // towards the total so that the workers finish
+            None => positions.clear(),
+        }
+
+        let total = progress
+            .positions
+            .fetch_add(positions.len(), Ordering::Relaxed)
+            + positions.len();
+        let done = progress.games_done.fetch_add(1, Ordering::Relaxed) + 1;
+        if done.is_multiple_of(100) || done == options.games {
+            let secs = progress.start.elapsed().as_secs_f64().max(0.001);
+            println!(
+                "{done}/{} games, {total} positions, {:.0} positions/s",
+                options.games,
+                total as f64 / secs
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Play one game, collecting the quiet positions along with their white-relative search scores.
+/// Returns the game result for white, or `None` if the game was abandoned because a search
+/// found no move
+fn play_game(
+    options: &DatagenOptions,
+    book: &[Board],
+    rng: &mut Rng,
+    tt: &TT,
+    positions: &mut Vec<(String, i32)>,
+) -> Option<f32> {
+    let mut threads_data = vec![ThreadData::new(&Board::new(), tt.slice())];
+    let (mut board, mut history) =
+        opening(book, options.plies, limits(options), rng, &mut threads_data);
+
+    let mut win_streak = 0;
+    let mut draw_streak = 0;
+    let mut plies = 0;
+
+    loop {
+        if let Some(result) = game_result(&board, &history) {
+            return Some(result);
+        }
+
+        let (score, best_move) = search(&board, &history, limits(options), &mut threads_data);
+        // a search without a move can't be trusted to continue the game
+        let best_move = best_move?;
+        let white_score = if board.player() == White {
+            score
+        } else {
+            -score
+        };
+
+        if is_quiet_label(&board, best_move, score) {
+            positions.push((board.fen(), white_score));
+        }
+
+        win_streak = match white_score {
+            s if s >= WIN_ADJ_SCORE => win_streak.max(0) + 1,
+            s if s <= -WIN_ADJ_SCORE => win_streak.min(0) - 1,
+            _ => 0,
+        };
+        if win_streak >= WIN_ADJ_PLIES {
+            return Some(1.0);
+        }
+        if win_streak <= -WIN_ADJ_PLIES {
+            return Some(0.0);
+        }
+
+        if plies >= DRAW_ADJ_MIN_PLY && score.abs() <= DRAW_ADJ_SCORE {
+            draw_streak += 1;
+        } else {
+            draw_streak = 0;
+        }
+        if draw_streak >= DRAW_ADJ_PLIES {
+            return Some(0.5);
+        }
+
+        history.push(board.hash());
+        assert!(board.make_move_only(best_move));
+        plies += 1;
+    }
+}
+
+/// A start position or book position with `plies` random plies played, rejecting lopsided results
+/// by a search with `limits`. Returns the position and the hashes of the positions before it
+pub fn opening(
+    book: &[Board],
+    plies: usize,
+    limits: TimeManager,
+    rng: &mut Rng,
+    threads_data: &mut [ThreadData],
+) -> (Board, Vec<u64>) {
+    'retry: loop {
+        let mut board = if book.is_empty() {
+            Board::new()
+        } else {
+            book[rng.below(book.len())]
+        };
+        let mut history = vec![];
+
+        for _ in 0..plies {
+            let legals = board.legal_moves();
+            if legals.is_empty() {
+                continue 'retry;
+            }
+            history.push(board.hash());
+            assert!(board.make_move_only(legals[rng.below(legals.len())]));
+        }
+
+        if game_result(&board, &history).is_some() {
+            continue;
+        }
+        let (score, _) = search(&board, &history, limits, threads_data);
+        if score.abs() <= OPENING_MAX_SCORE {
+            return (board, history);
+        }
+    }
+}
+
+fn limits(options: &DatagenOptions) -> TimeManager {
+    TimeManager::new(Instant::now()).fixed_nodes(Some(options.nodes))
+}
+
+/// Whether a searched position makes a useful training label: quiet positions with a quiet best
+/// move are evaluated well by a static network, and mate scores are not evaluations
+pub fn is_quiet_label(board: &Board, best_move: Move, score: i32) -> bool {
+    !board.in_check()
+        && !board.is_capture(best_move)
+        && best_move.promo().is_none()
+        && score.abs() < MIN_TB_WIN_SCORE
+}
+
+/// Search `board` silently with the limits of `time_manager`, where `history` holds the hashes of
+/// the previous positions in the game. The score is for the side to move
+pub fn search(
+    board: &Board,
+    history: &[u64],
+    time_manager: TimeManager,
+    threads_data: &mut [ThreadData],
+) -> (i32, Option<Move>) {
+    for t in threads_data.iter_mut() {
+        board.refresh_root_accumulator(t);
+        t.search_history.clear();
+        t.search_history.extend_from_slice(history);
+    }
+
+    let stop_signal = AtomicBool::new(false);
+    let node_counter = AtomicU64::new(0);
+    let mut info = SearchInfo::new(&stop_signal, &node_counter)
+        .time_manager(time_manager)
+        .stdout(false);
+
+    board.search(&mut info, threads_data)
+}
+
+/// The result for white if the game is over, `history` holds the hashes of the previous positions
+fn game_result(board: &Board, history: &[u64]) -> Option<f32> {
+    if board.legal_moves().is_empty() {
+        return Some(match (board.in_check(), board.player()) {
+            (false, _) => 0.5,
+            (true, White) => 0.0,
+            (true, Black) => 1.0,
+        });
+    }
+
+    if board.halfmove_clock() >= 100 {
+        return Some(0.5);
+    }
+
+    let repetitions = history
+        .iter()
+        .rev()
+        .take(board.halfmove_clock() as usize)
+        .filter(|&&hash| hash == board.hash())
+        .count();
+    if repetitions >= 2 {
+        return Some(0.5);
+    }
+
+    // bare kings, or a single minor piece
+    let can_mate = [Pawn, Rook, Queen].into_iter().any(|piece| {
+        let [white, black] = board.pieces(piece);
+        (white | black).is_not_empty()
+    });
+    if !can_mate && board.all_pieces().count_ones() <= 3 {
+        return Some(0.5);
+    }
+
+    None
+}
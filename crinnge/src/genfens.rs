@@ -0,0 +1,120 @@
+use std::{collections::HashSet, error::Error, time::Instant};
+
+use crinnge_lib::{board::Board, rng::Rng, thread_data::ThreadData, timeman::TimeManager, tt::TT};
+
+use crate::datagen::{load_book, opening};
+
+pub const USAGE: &str = "usage: crinnge genfens N seed S [book FILE|None]";
+
+/// Depth of the search that rejects lopsided openings
+const FILTER_DEPTH: usize = 6;
+
+#[derive(Clone, Debug)]
+pub struct GenfensOptions {
+    pub count: usize,
+    pub seed: u64,
+    pub book: Option<String>,
+}
+
+impl GenfensOptions {
+    /// Parse `N seed S [book FILE]`, where a book of `None` means no book
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let count = args
+            .next()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| USAGE.to_string())?;
+        let mut options = Self {
+            count,
+            seed: 0,
+            book: None,
+        };
+
+        while let Some(name) = args.next() {
+            match name.as_str() {
+                "seed" => {
+                    options.seed = args
+                        .next()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or_else(|| USAGE.to_string())?
+                }
+                "book" => {
+                    let book = args.next().ok_or_else(|| USAGE.to_string())?;
+                    options.book = (book != "None").then_some(book);
+                }
+                // testing frameworks send quit after the command
+                "quit" => break,
+                _ => return Err(format!("Unknown genfens option {name}\n{USAGE}")),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Print `count` distinct openings of a few random plies as `info string genfens <fen>` lines.
+/// The same seed and book always give the same openings
+pub fn run(options: &GenfensOptions) -> Result<(), Box<dyn Error>> {
+    for fen in openings(options)? {
+        println!("info string genfens {fen}");
+    }
+
+    Ok(())
+}
+
+fn openings(options: &GenfensOptions) -> Result<Vec<String>, Box<dyn Error>> {
+    let book = match &options.book {
+        Some(path) => load_book(path)?,
+        None => vec![],
+    };
+
+    let mut rng = Rng::new(options.seed);
+    let tt = TT::new(16);
+    let mut threads_data = vec![ThreadData::new(&Board::new(), tt.slice())];
+    let limits = TimeManager::new(Instant::now()).fixed_depth(Some(FILTER_DEPTH));
+    let mut seen = HashSet::new();
+    let mut fens = vec![];
+    let mut attempts = 0;
+
+    while fens.len() < options.count {
+        attempts += 1;
+        if attempts > 100 * options.count {
+            return Err(format!("Only found {} distinct openings", fens.len()).into());
+        }
+
+        // book positions only need a little extra variety, and an odd number of plies some of
+        // the time lets both sides start
+        let plies = if book.is_empty() { 8 } else { 2 } + rng.below(2);
+        let (board, _) = opening(&book, plies, limits, &mut rng, &mut threads_data);
+
+        if seen.insert(board.hash()) {
+            fens.push(board.fen());
+        }
+    }
+
+    Ok(fens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crinnge_lib::board::Board;
+
+    use super::{openings, GenfensOptions};
+
+    #[test]
+    fn test_genfens_deterministic() {
+        let args = ["4", "seed", "42", "book", "None", "quit"];
+        let options = GenfensOptions::parse(args.into_iter().map(String::from)).unwrap();
+        assert_eq!(
+            (options.count, options.seed, options.book.clone()),
+            (4, 42, None)
+        );
+
+        let fens = openings(&options).unwrap();
+        assert_eq!(fens.len(), 4);
+        assert_eq!(fens, openings(&options).unwrap());
+        for (i, fen) in fens.iter().enumerate() {
+            assert!(Board::from_fen(fen).is_some());
+            assert!(!fens[..i].contains(fen));
+        }
+    }
+}
@@ -1,19 +1,28 @@
+mod datagen;
+mod genfens;
+mod relabel;
 mod uci;
 
 use std::{
     env,
     error::Error,
+    io::stdin,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
-        Mutex,
+        mpsc::{self, Receiver},
+        Arc,
     },
-    time::Instant,
+    thread,
+    time::{Duration, Instant},
 };
 
 use crinnge_lib::{
     board::Board,
-    moves::MoveList,
-    nnue::{Accumulator, NNUE},
+    moves::{MoveList, CHESS960},
+    nnue::{
+        network, network_description, output_bucket, set_network, use_default_network, Accumulator,
+        Network,
+    },
     search::{
         info::{SearchInfo, UCI_QUIT},
         options::SearchOptions,
@@ -23,48 +32,146 @@ use crinnge_lib::{
     tt::TT,
     types::*,
 };
-use uci::stdin_reader;
 
 pub static VERSION: &str = env!("CARGO_PKG_VERSION");
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut board = Board::new();
-    let mut tt = TT::new(8);
-    let mut search_options = SearchOptions::default();
-    let mut threads_data = vec![ThreadData::new(&board, tt.slice()); search_options.threads];
+/// Number of commands the engine thread has started on, every earlier command is finished
+static COMMANDS_STARTED: AtomicU64 = AtomicU64::new(0);
 
-    if env::args().nth(1) == Some("bench".to_string()) {
-        let start_time = Instant::now();
-        let time_manager = TimeManager::new(start_time).fixed_depth(Some(18));
-        let node_counter = AtomicU64::new(0);
-        let stop_signal = AtomicBool::new(false);
-        let mut info = SearchInfo::new(&stop_signal, &node_counter)
-            .time_manager(time_manager)
-            .stdout(false);
-
-        board.search(&mut info, &mut threads_data);
-
-        let nodes = info.global_node_count();
-        let elapsed = time_manager.elapsed().as_millis() as u64;
-        println!(
-            "{} Nodes {} NPS",
-            info.global_node_count(),
-            nodes * 1000 / elapsed
-        );
+/// Flags the UCI thread sets for one go command, so a later go can't undo a stop sent to an
+/// earlier search
+#[derive(Debug, Default)]
+struct SearchSignals {
+    stop: AtomicBool,
+    /// Also set on stop to end pondering
+    ponderhit: AtomicBool,
+}
 
+fn main() -> Result<(), Box<dyn Error>> {
+    if env::args().nth(1) == Some("bench".to_string()) {
+        bench();
         return Ok(());
     }
+    if env::args().nth(1) == Some("datagen".to_string()) {
+        let options = datagen::DatagenOptions::parse(env::args().skip(2))?;
+        return datagen::run(&options);
+    }
+    if env::args().nth(1) == Some("relabel".to_string()) {
+        let options = relabel::RelabelOptions::parse(env::args().skip(2))?;
+        return relabel::run(&options);
+    }
+    // testing frameworks pass the whole genfens command as one argument
+    let args: Vec<String> = env::args()
+        .skip(1)
+        .flat_map(|arg| arg.split_whitespace().map(String::from).collect::<Vec<_>>())
+        .collect();
+    if args.first().is_some_and(|arg| arg == "genfens") {
+        let options = genfens::GenfensOptions::parse(args.into_iter().skip(1))?;
+        return genfens::run(&options);
+    }
+
+    // the engine thread processes commands in order, leaving this thread free to handle
+    // commands that need an answer during a search
+    let (tx, rx) = mpsc::channel();
+    let engine = thread::spawn(move || engine_loop(rx));
+    // index and signals of go commands sent to the engine thread, which drops its copy of the
+    // signals once it is done
+    let mut searches: Vec<(u64, Arc<SearchSignals>)> = Vec::new();
+    let mut sent = 0;
 
-    let stdin_rx = Mutex::new(stdin_reader());
-    'command: loop {
-        let Ok(line) = stdin_rx.lock().unwrap().recv() else {
-            UCI_QUIT.store(true, Ordering::Relaxed);
+    for line in stdin().lines() {
+        let Ok(line) = line else {
+            eprintln!("info string stdin read error");
             break;
         };
+
+        searches.retain(|(_, signals)| Arc::strong_count(signals) > 1);
+        match line.trim().to_ascii_lowercase().as_str() {
+            // stopped searches are no longer in flight, a later isready waits for their bestmove
+            "stop" => stop_searches(&mut searches),
+            "ponderhit" => {
+                for (_, signals) in searches.iter() {
+                    signals.ponderhit.store(true, Ordering::Relaxed);
+                }
+            }
+            // a search would hold up a queued isready, so answer it here once the commands
+            // before the search have had their output
+            "isready" if !searches.is_empty() => {
+                let (index, signals) = &searches[0];
+                while COMMANDS_STARTED.load(Ordering::Acquire) <= *index
+                    && Arc::strong_count(signals) > 1
+                {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                println!("readyok");
+            }
+            "quit" => break,
+            command => {
+                // the search may not start until earlier commands are done, but a stop or
+                // ponderhit after this go must still reach it
+                let signals = (command.split(' ').next() == Some("go")).then(|| {
+                    let signals = Arc::new(SearchSignals::default());
+                    searches.push((sent, Arc::clone(&signals)));
+                    signals
+                });
+                let _ = tx.send((line, signals));
+                sent += 1;
+            }
+        }
+    }
+
+    // stop any running search and let the engine thread finish
+    UCI_QUIT.store(true, Ordering::Relaxed);
+    stop_searches(&mut searches);
+    drop(tx);
+    let _ = engine.join();
+
+    Ok(())
+}
+
+fn stop_searches(searches: &mut Vec<(u64, Arc<SearchSignals>)>) {
+    for (_, signals) in searches.drain(..) {
+        signals.ponderhit.store(true, Ordering::Relaxed);
+        signals.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn bench() {
+    let board = Board::new();
+    let tt = TT::new(8);
+    let mut threads_data = vec![ThreadData::new(&board, tt.slice())];
+
+    let start_time = Instant::now();
+    let time_manager = TimeManager::new(start_time).fixed_depth(Some(18));
+    let node_counter = AtomicU64::new(0);
+    let stop_signal = AtomicBool::new(false);
+    let mut info = SearchInfo::new(&stop_signal, &node_counter)
+        .time_manager(time_manager)
+        .stdout(false);
+
+    board.search(&mut info, &mut threads_data);
+
+    let nodes = info.global_node_count();
+    let elapsed = time_manager.elapsed().as_millis() as u64;
+    println!(
+        "{} Nodes {} NPS",
+        info.global_node_count(),
+        nodes * 1000 / elapsed
+    );
+}
+
+fn engine_loop(commands: Receiver<(String, Option<Arc<SearchSignals>>)>) {
+    let mut board = Board::new();
+    let mut tt = TT::new(8);
+    let mut search_options = SearchOptions::default();
+    let mut threads_data = vec![ThreadData::new(&board, tt.slice()); search_options.threads];
+
+    'command: for (line, signals) in commands {
+        COMMANDS_STARTED.fetch_add(1, Ordering::Release);
         let command = uci::parse(&line, &mut search_options);
 
         if let Err(e) = command {
-            eprintln!("info string {:?}", e);
+            eprintln!("info string {e}");
             continue;
         }
 
@@ -99,22 +206,44 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
                 board = test_board;
                 for t in threads_data.iter_mut() {
-                    board.refresh_accumulator(&mut t.accumulators[0]);
+                    board.refresh_root_accumulator(t);
                     t.search_history.clone_from(&prehistory);
                 }
             }
             uci::UciCommand::Fen => {
                 println!("info string {}", board.fen());
             }
-            uci::UciCommand::SetOption(name, _) => {
-                if matches!(name.as_str(), "threads" | "hash") {
+            uci::UciCommand::SetOption(name, value) => match name.as_str() {
+                "threads" | "hash" => {
                     drop(threads_data);
                     tt.resize(search_options.hash);
                     threads_data =
                         vec![ThreadData::new(&board, tt.slice()); search_options.threads];
                 }
-            }
+                "uci_chess960" => CHESS960.store(value == "true", Ordering::Relaxed),
+                "evalfile" => {
+                    if value == "<default>" {
+                        use_default_network();
+                    } else {
+                        match Network::load(&value) {
+                            Ok(net) => set_network(net, &value),
+                            Err(e) => {
+                                println!("info string {e}, using the default network");
+                                use_default_network();
+                            }
+                        }
+                    }
+                    println!("info string {}", network_description());
+                    for t in threads_data.iter_mut() {
+                        t.update_network(&board);
+                    }
+                }
+                _ => {}
+            },
             uci::UciCommand::Go(options) => {
+                let Some(signals) = signals else {
+                    continue;
+                };
                 if let Some(depth) = options.perft {
                     perft(&board, depth);
                     continue;
@@ -142,27 +271,49 @@ fn main() -> Result<(), Box<dyn Error>> {
                     ntm_inc,
                     movestogo: options.movestogo,
                 };
-                let time_manager = TimeManager::new(Instant::now())
-                    .time_limited(time_data, search_options.time_options())
+                let mut time_manager = TimeManager::new(Instant::now());
+                // go mate without a clock searches until the mate is found
+                if options.mate.is_none() || options.wtime.is_some() || options.btime.is_some() {
+                    time_manager =
+                        time_manager.time_limited(time_data, search_options.time_options());
+                }
+                let time_manager = time_manager
                     .fixed_depth(options.depth)
                     .fixed_nodes(options.nodes)
                     .fixed_time_millis(options.movetime)
-                    .infinite(options.infinite);
+                    .infinite(options.infinite)
+                    .fixed_mate(options.mate)
+                    .ponder(options.ponder);
+
+                let mut search_moves = vec![];
+                let legals = board.legal_moves();
+                for mv in options.searchmoves.iter() {
+                    if let Some(legal) = legals.iter().find(|m| &m.coords() == mv) {
+                        search_moves.push(*legal);
+                    } else {
+                        eprintln!("info string Illegal move: {mv}");
+                        continue 'command;
+                    }
+                }
+                for t in threads_data.iter_mut() {
+                    t.search_moves.clone_from(&search_moves);
+                }
 
-                let stop_signal = AtomicBool::new(false);
                 let global_nodes = AtomicU64::new(0);
-                let mut info = SearchInfo::new(&stop_signal, &global_nodes)
+                let mut info = SearchInfo::new(&signals.stop, &global_nodes)
                     .time_manager(time_manager)
                     .options(search_options)
-                    .stdin(Some(&stdin_rx));
+                    .ponderhit(Some(&signals.ponderhit));
 
                 board.search(&mut info, &mut threads_data);
             }
             uci::UciCommand::Eval => {
-                let mut acc = Accumulator::new();
-                board.refresh_accumulator(&mut acc);
-                let weval = NNUE.evaluate(&acc.white);
-                let beval = NNUE.evaluate(&acc.black);
+                let net = network();
+                let mut acc = Accumulator::new(&net);
+                board.refresh_accumulator(&net, &mut acc);
+                let bucket = output_bucket(board.all_pieces().count_ones());
+                let weval = net.evaluate(&acc.white, bucket);
+                let beval = net.evaluate(&acc.black, bucket);
                 println!("info string white eval: {weval}");
                 println!("info string black eval: {beval}");
             }
@@ -171,7 +322,6 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
     }
-    Ok(())
 }
 
 fn perft(board: &Board, depth: usize) {
@@ -188,7 +338,7 @@ fn perft(board: &Board, depth: usize) {
     for mv in noisy.iter_moves().chain(quiet.iter_moves()) {
         let mut next = *board;
         if next.make_move_only(*mv) {
-            let subcount = _perft(&next, depth - 1);
+            let subcount = next.perft(depth - 1);
             count += subcount;
             println!("{}: {}", mv.coords(), subcount)
         }
@@ -198,22 +348,3 @@ fn perft(board: &Board, depth: usize) {
 
     println!("\nTotal: {count}\tNPS: {nps}");
 }
-
-fn _perft(board: &Board, depth: usize) -> usize {
-    if depth == 0 {
-        return 1;
-    }
-    let mut count = 0usize;
-    let [mut noisy, mut quiet] = [MoveList::new(); 2];
-
-    board.generate_moves_into(&mut noisy, &mut quiet);
-
-    for mv in noisy.iter_moves().chain(quiet.iter_moves()) {
-        let mut next = *board;
-        if next.make_move_only(*mv) {
-            count += _perft(&next, depth - 1)
-        }
-    }
-
-    count
-}
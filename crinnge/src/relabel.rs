@@ -0,0 +1,225 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Instant,
+};
+
+use crinnge_lib::{
+    board::Board, data::parse_line, thread_data::ThreadData, timeman::TimeManager, tt::TT, types::*,
+};
+
+use crate::datagen::{is_quiet_label, search};
+
+pub const USAGE: &str = "usage: crinnge relabel in FILE out FILE [threads N] [depth N] [nodes N] \
+                         [hash MB]";
+
+/// Positions read and searched at a time, keeping memory use flat for large files
+const BLOCK_SIZE: usize = 16384;
+
+#[derive(Clone, Debug)]
+pub struct RelabelOptions {
+    pub input: String,
+    pub out: String,
+    pub threads: usize,
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub hash: usize,
+}
+
+impl RelabelOptions {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            input: String::new(),
+            out: String::new(),
+            threads: 1,
+            depth: None,
+            nodes: None,
+            hash: 16,
+        };
+
+        while let Some(name) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {name}\n{USAGE}"))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid value for {name}: {value}"))
+            };
+            match name.as_str() {
+                "in" => options.input = value,
+                "out" => options.out = value,
+                "threads" => options.threads = number()?.max(1) as usize,
+                "depth" => options.depth = Some(number()?.max(1) as usize),
+                "nodes" => options.nodes = Some(number()?.max(1)),
+                "hash" => options.hash = number()?.max(1) as usize,
+                _ => return Err(format!("Unknown relabel option {name}\n{USAGE}")),
+            }
+        }
+
+        if options.input.is_empty() || options.out.is_empty() {
+            return Err(format!("Input and output files are required\n{USAGE}"));
+        }
+        if options.depth.is_none() && options.nodes.is_none() {
+            options.depth = Some(8);
+        }
+
+        Ok(options)
+    }
+
+    fn limits(&self) -> TimeManager {
+        TimeManager::new(Instant::now())
+            .fixed_depth(self.depth)
+            .fixed_nodes(self.nodes)
+    }
+}
+
+/// Re-search every position of a `fen | score | result` file and write it back with the new
+/// score, keeping the result. Positions in check, with a noisy best move or with a mate score
+/// are dropped
+pub fn run(options: &RelabelOptions) -> Result<(), Box<dyn Error>> {
+    let mut lines = BufReader::new(File::open(&options.input)?).lines();
+    let mut writer = BufWriter::new(File::create(&options.out)?);
+    let tts: Vec<_> = (0..options.threads)
+        .map(|_| TT::new(options.hash))
+        .collect();
+
+    let start = Instant::now();
+    let (mut read, mut kept) = (0, 0);
+
+    loop {
+        let mut block = vec![];
+        for line in lines.by_ref().take(BLOCK_SIZE) {
+            let line = line?;
+            read += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (board, _, result) =
+                parse_line(&line).ok_or_else(|| format!("Invalid data on line {read}: {line}"))?;
+            let fen = line
+                .split('|')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            block.push((fen, board, result));
+        }
+        if block.is_empty() {
+            break;
+        }
+
+        let boards: Vec<_> = block.iter().map(|(_, board, _)| *board).collect();
+        for (i, score) in relabel_block(options, &boards, &tts) {
+            let (fen, _, result) = &block[i];
+            writeln!(writer, "{fen} | {score} | {result:.1}")?;
+            kept += 1;
+        }
+
+        let secs = start.elapsed().as_secs_f64().max(0.001);
+        println!(
+            "{read} lines read, {kept} positions relabelled, {:.0} positions/s",
+            read as f64 / secs
+        );
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Search `boards` across threads, returning the index and white relative score of each position
+/// that is kept, in order
+fn relabel_block(options: &RelabelOptions, boards: &[Board], tts: &[TT]) -> Vec<(usize, i32)> {
+    let next = AtomicUsize::new(0);
+
+    let mut scores: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = tts
+            .iter()
+            .map(|tt| {
+                let next = &next;
+                s.spawn(move || {
+                    let mut threads_data = vec![ThreadData::new(&Board::new(), tt.slice())];
+                    let mut scores = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(board) = boards.get(i) else {
+                            break;
+                        };
+                        // positions in check are dropped whatever the search finds
+                        if board.in_check() {
+                            continue;
+                        }
+
+                        let (score, best_move) =
+                            search(board, &[], options.limits(), &mut threads_data);
+                        let Some(best_move) = best_move else {
+                            continue;
+                        };
+                        if is_quiet_label(board, best_move, score) {
+                            let white_score = if board.player() == White {
+                                score
+                            } else {
+                                -score
+                            };
+                            scores.push((i, white_score));
+                        }
+                    }
+                    scores
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("relabel thread panicked"))
+            .collect()
+    });
+
+    scores.sort_unstable_by_key(|&(i, _)| i);
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{run, RelabelOptions};
+
+    #[test]
+    fn test_relabel_file() {
+        let dir = env::temp_dir();
+        let input = dir.join(format!("crinnge-relabel-{}.txt", process::id()));
+        let out = input.with_extension("out.txt");
+        fs::write(
+            &input,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 0 | 1.0\n\
+             4k3/8/8/8/8/8/8/4K2r w - - 0 1 | -500 | 0.0\n\
+             4k3/8/8/3q4/8/8/3Q4/4K3 w - - 0 1 | 0 | 0.5\n",
+        )
+        .unwrap();
+
+        let args = ["in", input.to_str().unwrap(), "out", out.to_str().unwrap()];
+        let mut options = RelabelOptions::parse(args.into_iter().map(String::from)).unwrap();
+        options.depth = Some(4);
+        run(&options).unwrap();
+
+        let relabelled = fs::read_to_string(&out).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::remove_file(out).unwrap();
+
+        // the position in check and the one with a winning capture are dropped
+        let lines: Vec<_> = relabelled.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let fields: Vec<_> = lines[0].split(" | ").collect();
+        assert_eq!(
+            fields[0],
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert!(fields[1].parse::<i32>().unwrap().abs() < 200);
+        assert_eq!(fields[2], "1.0");
+    }
+}
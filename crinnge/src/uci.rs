@@ -1,15 +1,9 @@
-use std::{
-    io::stdin,
-    sync::{
-        atomic::Ordering,
-        mpsc::{self, Receiver},
-    },
-    thread,
-};
+use std::fmt::Display;
 
 use crinnge_lib::{
     board::Board,
-    search::{info::UCI_QUIT, options::SearchOptions, INF, MAX_DEPTH},
+    nnue::network_description,
+    search::{options::SearchOptions, INF, MAX_DEPTH},
 };
 
 use crate::VERSION;
@@ -17,6 +11,7 @@ use crate::VERSION;
 pub struct GoCommand {
     pub perft: Option<usize>,
     pub infinite: bool,
+    pub ponder: bool,
     pub wtime: Option<i64>,
     pub btime: Option<i64>,
     pub winc: Option<i64>,
@@ -25,6 +20,8 @@ pub struct GoCommand {
     pub movestogo: Option<usize>,
     pub depth: Option<usize>,
     pub nodes: Option<u64>,
+    pub mate: Option<usize>,
+    pub searchmoves: Vec<String>,
 }
 pub enum UciCommand {
     Uci,
@@ -53,6 +50,15 @@ pub enum UciError {
     UnknownOption(String),
 }
 
+impl Display for UciError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownOption(name) => write!(f, "Unknown option: {name}"),
+            e => write!(f, "{e:?}"),
+        }
+    }
+}
+
 pub fn parse(command: &str, options: &mut SearchOptions) -> Result<UciCommand, UciError> {
     let parts: Vec<_> = command.split(' ').collect();
 
@@ -109,6 +115,22 @@ pub fn parse_position_command(parts: &[&str]) -> Result<UciCommand, UciError> {
     Ok(UciCommand::Position { start_fen, moves })
 }
 
+const GO_KEYWORDS: [&str; 13] = [
+    "perft",
+    "infinite",
+    "ponder",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movetime",
+    "movestogo",
+    "depth",
+    "nodes",
+    "mate",
+    "searchmoves",
+];
+
 fn parse_go_command(parts: &[&str]) -> Result<UciCommand, UciError> {
     let rest = &parts[1..];
 
@@ -120,6 +142,7 @@ fn parse_go_command(parts: &[&str]) -> Result<UciCommand, UciError> {
     };
 
     let infinite = rest.contains(&"infinite");
+    let ponder = rest.contains(&"ponder");
 
     let wtime = if let Some(i) = rest.iter().position(|&w| w == "wtime") {
         let n = rest.get(i + 1).ok_or(UciError::IncompleteCommand)?;
@@ -174,9 +197,28 @@ fn parse_go_command(parts: &[&str]) -> Result<UciCommand, UciError> {
         None
     };
 
+    let mate = if let Some(i) = rest.iter().position(|&w| w == "mate") {
+        let n = rest.get(i + 1).ok_or(UciError::IncompleteCommand)?;
+        Some(n.parse::<usize>().map_err(|_| UciError::InvalidGoCommand)?)
+    } else {
+        None
+    };
+
+    // searchmoves takes every move up to the next keyword
+    let searchmoves = if let Some(i) = rest.iter().position(|&w| w == "searchmoves") {
+        rest[i + 1..]
+            .iter()
+            .take_while(|w| !GO_KEYWORDS.contains(w))
+            .map(|w| w.to_string())
+            .collect()
+    } else {
+        vec![]
+    };
+
     Ok(UciCommand::Go(GoCommand {
         perft,
         infinite,
+        ponder,
         wtime,
         btime,
         winc,
@@ -185,6 +227,8 @@ fn parse_go_command(parts: &[&str]) -> Result<UciCommand, UciError> {
         movestogo,
         depth,
         nodes,
+        mate,
+        searchmoves,
     }))
 }
 
@@ -204,9 +248,14 @@ fn parse_setoption_command(
         return Err(UciError::InvalidSetoptionCommand);
     }
 
-    let Some(value) = parts.get(4) else {
+    // values like file paths may contain spaces
+    let value = parts
+        .get(4..)
+        .ok_or(UciError::InvalidSetoptionCommand)?
+        .join(" ");
+    if value.is_empty() {
         return Err(UciError::InvalidSetoptionCommand);
-    };
+    }
 
     match name.to_ascii_lowercase().as_str() {
         "threads" => {
@@ -221,6 +270,19 @@ fn parse_setoption_command(
             };
             options.hash = n;
         }
+        "multipv" => {
+            let Ok(n @ 1..=218) = value.parse::<usize>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.multi_pv = n;
+        }
+        "ponder" | "uci_chess960" => {
+            if !matches!(value.as_str(), "true" | "false") {
+                return Err(UciError::InvalidSetoptionCommand);
+            }
+        }
+        // the network is loaded by the engine
+        "evalfile" => {}
         "aspwindowinit" => {
             let Ok(n @ 1..=INF) = value.parse::<i32>() else {
                 return Err(UciError::InvalidSetoptionCommand);
@@ -281,6 +343,24 @@ fn parse_setoption_command(
             };
             options.rfp_margin = n;
         }
+        "rfpimprovingmargin" => {
+            let Ok(n @ 0..=INF) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.rfp_improving_margin = n;
+        }
+        "nmpimprovingmargin" => {
+            let Ok(n @ 0..=INF) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.nmp_improving_margin = n;
+        }
+        "lmrimprovingreduction" => {
+            let Ok(n @ 0..=MAX_DEPTH) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.lmr_improving_reduction = n;
+        }
         "iirmindepth" => {
             let Ok(n @ 1..=MAX_DEPTH) = value.parse::<i32>() else {
                 return Err(UciError::InvalidSetoptionCommand);
@@ -311,12 +391,61 @@ fn parse_setoption_command(
             };
             options.see_quiet_margin = n;
         }
+        "lmrhistorydivisor" => {
+            let Ok(n @ 1..=65536) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.lmr_history_divisor = n;
+        }
+        "semindepth" => {
+            let Ok(n @ 1..=MAX_DEPTH) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.se_min_depth = n;
+        }
+        "settdepthmargin" => {
+            let Ok(n @ 0..=MAX_DEPTH) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.se_tt_depth_margin = n;
+        }
+        "sebetamargin" => {
+            let Ok(n @ 0..=100) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.se_beta_margin = n;
+        }
+        "sedoublemargin" => {
+            let Ok(n @ 0..=1000) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.se_double_margin = n;
+        }
+        "setriplemargin" => {
+            let Ok(n @ 0..=1000) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.se_triple_margin = n;
+        }
+        "semaxdoubleextensions" => {
+            let Ok(n @ 0..=MAX_DEPTH) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.se_max_double_extensions = n;
+        }
+        "lmpmaxdepth" => {
+            // the LMP margins table only goes up to depth 31
+            let Ok(n @ 0..=31) = value.parse::<i32>() else {
+                return Err(UciError::InvalidSetoptionCommand);
+            };
+            options.lmp_max_depth = n;
+        }
         _ => return Err(UciError::UnknownOption(name.to_string())),
     }
 
     Ok(UciCommand::SetOption(
         name.to_ascii_lowercase().to_string(),
-        value.to_string(),
+        value,
     ))
 }
 
@@ -324,26 +453,6 @@ pub fn print_uci_message() {
     println!("id name CriNNge {}", VERSION);
     println!("id author Algorhythm");
     print!("{}", SearchOptions::default());
+    println!("info string {}", network_description());
     println!("uciok");
 }
-
-pub fn stdin_reader() -> Receiver<String> {
-    let (tx, rx) = mpsc::channel();
-
-    thread::spawn(move || {
-        for line in stdin().lines() {
-            let Ok(line) = line else {
-                eprintln!("info string stdin read error");
-                UCI_QUIT.store(true, Ordering::Relaxed);
-                break;
-            };
-            let _ = tx.send(line.clone());
-            if line.starts_with("quit") {
-                UCI_QUIT.store(true, Ordering::Relaxed);
-                break;
-            }
-        }
-    });
-
-    rx
-}
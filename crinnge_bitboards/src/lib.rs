@@ -332,6 +332,10 @@ impl Square {
     pub fn flip(&self) -> Square {
         Square(self.0 ^ 56)
     }
+
+    pub fn mirror(&self) -> Square {
+        Square(self.0 ^ 7)
+    }
 }
 
 impl Deref for Square {
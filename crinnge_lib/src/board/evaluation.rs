@@ -1,15 +1,13 @@
-use crate::types::*;
 use crate::{search::TB_WIN_SCORE, thread_data::ThreadData};
 
-use super::{Board, NNUE};
+use super::{output_bucket, Board};
 
 impl Board {
     pub fn evaluate(&self, t: &mut ThreadData, ply: usize) -> i32 {
-        let acc = match self.player {
-            White => &t.accumulators[ply].white,
-            Black => &t.accumulators[ply].black,
-        };
-        let eval = NNUE.evaluate(acc);
+        self.update_accumulator(t, ply, self.player);
+        let acc = &t.accumulators[ply].acc[self.player];
+        let bucket = output_bucket(self.all_pieces().count_ones());
+        let eval = t.network.evaluate(acc, bucket);
 
         // TODO: material scaling
         // TODO: 50mr scaling
@@ -58,21 +58,37 @@ impl Board {
         }
 
         for chr in parts.get(2)?.chars() {
-            let file = match chr.to_ascii_lowercase() {
-                '-' => break,
-                'k' => 7,
-                'q' => 0,
-                n @ 'a'..='h' => n as u32 - 'a' as u32,
-                _ => return None,
-            };
+            if chr == '-' {
+                break;
+            }
             let color = if chr.is_ascii_lowercase() {
                 Black
             } else {
                 White
             };
-            let castle = Square::from(7 * 8 * color as u32 + file).bitboard();
-            let king_file = board.kings[color].first_square().file();
-            let kingside = file > king_file as u32;
+            if board.kings[color].is_empty() {
+                return None;
+            }
+            let back_rank = 7 * 8 * color as u32;
+            let king_file = board.kings[color].first_square().file() as u32;
+            let rooks = board.rooks[color];
+            let rook_on =
+                |file: &u32| (Square::from(back_rank + file).bitboard() & rooks).is_not_empty();
+
+            let file = match chr.to_ascii_lowercase() {
+                // X-FEN: the outermost rook on each side of the king
+                'k' => (king_file + 1..8).rev().find(rook_on),
+                'q' => (0..king_file).find(rook_on),
+                // Shredder-FEN: the file of the castling rook
+                n @ 'a'..='h' => Some(n as u32 - 'a' as u32),
+                _ => return None,
+            };
+            // ignore castling rights without a rook to castle with
+            let Some(file) = file else {
+                continue;
+            };
+            let castle = Square::from(back_rank + file).bitboard();
+            let kingside = file > king_file;
             board.castles[color][kingside as usize] = castle;
         }
 
@@ -107,7 +123,17 @@ impl Board {
         Some(board)
     }
 
+    /// FEN with X-FEN castling rights, which matches standard FEN for standard positions
     pub fn fen(&self) -> String {
+        self._fen(false)
+    }
+
+    /// FEN with Shredder-FEN castling rights, always naming the castling rook's file
+    pub fn shredder_fen(&self) -> String {
+        self._fen(true)
+    }
+
+    fn _fen(&self, shredder: bool) -> String {
         let mut fen = String::new();
 
         for rank in (0..8).rev() {
@@ -157,18 +183,36 @@ impl Board {
             fen.push_str(" -");
         } else {
             fen.push(' ');
-            // TODO: FRC Castling
-            if self.castles[White][1].is_not_empty() {
-                fen.push('K');
-            }
-            if self.castles[White][0].is_not_empty() {
-                fen.push('Q');
-            }
-            if self.castles[Black][1].is_not_empty() {
-                fen.push('k');
-            }
-            if self.castles[Black][0].is_not_empty() {
-                fen.push('q');
+            for color in [White, Black] {
+                let king_file = self.kings[color].first_square().file();
+                let rooks = self.rooks[color];
+                for kingside in [true, false] {
+                    let castle = self.castles[color][kingside as usize];
+                    if castle.is_empty() {
+                        continue;
+                    }
+                    let file = castle.first_square().file();
+
+                    // X-FEN only names the file when there is a rook further out than the castling rook
+                    let outermost = rooks
+                        .filter(|sq| sq.rank() == castle.first_square().rank())
+                        .filter(|sq| (sq.file() > king_file) == kingside)
+                        .all(|sq| (sq.file() > file) != kingside || sq.file() == file);
+                    let letter = if !shredder && outermost {
+                        if kingside {
+                            'k'
+                        } else {
+                            'q'
+                        }
+                    } else {
+                        (b'a' + file as u8) as char
+                    };
+                    fen.push(if color == White {
+                        letter.to_ascii_uppercase()
+                    } else {
+                        letter
+                    });
+                }
             }
         }
 
@@ -196,4 +240,29 @@ mod tests {
         let board = Board::from_fen(fen).unwrap();
         assert!(fen == board.fen());
     }
+
+    #[test]
+    fn test_960_fen_equality() {
+        // X-FEN only names the rook file when a rook is further out on the same side
+        let fen = "1r2k1r1/8/8/8/8/8/8/RR2K2R w KBkq - 0 1";
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(fen, board.fen());
+        assert_eq!(
+            "1r2k1r1/8/8/8/8/8/8/RR2K2R w HBgb - 0 1",
+            board.shredder_fen()
+        );
+
+        // Shredder-FEN and X-FEN describe the same castling rights
+        let shredder = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+        let board = Board::from_fen(shredder).unwrap();
+        assert_eq!(shredder, board.shredder_fen());
+        assert_eq!(
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9",
+            board.fen()
+        );
+        assert_eq!(
+            board.castles(),
+            Board::from_fen(&board.fen()).unwrap().castles()
+        );
+    }
 }
@@ -11,6 +11,12 @@ use lookups::*;
 
 use crate::{moves::*, nnue::*, thread_data::ThreadData, types::*};
 
+/// King and rook destinations for castling, indexed by color and side (queenside, kingside)
+pub const CASTLE_DESTS: [[(Square, Square); 2]; 2] = [
+    [(Square::C1, Square::D1), (Square::G1, Square::F1)],
+    [(Square::C8, Square::D8), (Square::G8, Square::F8)],
+];
+
 #[derive(Copy, Clone, Debug)]
 pub struct Board {
     pawns: [BitBoard; 2],
@@ -83,23 +89,62 @@ impl Board {
 
     pub fn make_move_nnue(&mut self, mv: Move, t: &mut ThreadData, ply: usize) -> bool {
         let mut updates = MoveUpdates::new();
+        let piece = self.piece_on(mv.from());
         if !self._make_move(mv, &mut updates) {
             return false;
         }
-        let (front, back) = t.accumulators.split_at_mut(ply + 1);
-        let before = front.last_mut().unwrap();
-        let after = back.first_mut().unwrap();
+        let kings = [self.king_square(White), self.king_square(Black)];
+        t.accumulators[ply + 1].set_pending(updates, kings);
+        t.move_stack[ply] = piece.map(|piece| (piece, mv.to()));
+
+        true
+    }
+
+    /// Bring the `persp` accumulator at `ply` up to date, applying the pending updates since the
+    /// last computed ply, or refreshing it if a king move in between changed its layout
+    pub fn update_accumulator(&self, t: &mut ThreadData, ply: usize, persp: Color) {
+        let net = &*t.network;
+        let stack = &mut t.accumulators;
+
+        // the root accumulator is always computed
+        let mut last_computed = ply;
+        while !stack[last_computed].computed[persp] {
+            if stack[last_computed].updates.needs_refresh(persp) {
+                t.finny_table
+                    .refresh(net, self, persp, &mut stack[ply].acc[persp]);
+                stack[ply].computed[persp] = true;
+                return;
+            }
+            last_computed -= 1;
+        }
 
-        before.apply_into(after, updates);
+        for p in last_computed + 1..=ply {
+            let (front, back) = stack.split_at_mut(p);
+            let (before, after) = (&front[p - 1], &mut back[0]);
+            apply_updates_into(
+                net,
+                &before.acc[persp],
+                &mut after.acc[persp],
+                after.updates,
+                persp,
+                after.kings[persp],
+            );
+            after.computed[persp] = true;
+        }
 
         debug_assert!({
-            let mut test = Accumulator::new();
-            self.refresh_accumulator(&mut test);
+            let mut test = Accumulator::new(net);
+            self.refresh_accumulator(net, &mut test);
 
-            test == t.accumulators[ply + 1]
+            test[persp] == t.accumulators[ply].acc[persp]
         });
+    }
 
-        true
+    /// Set the root accumulator from scratch
+    pub fn refresh_root_accumulator(&self, t: &mut ThreadData) {
+        let root = &mut t.accumulators[0];
+        self.refresh_accumulator(&t.network, &mut root.acc);
+        root.computed = [true; 2];
     }
 
     pub fn make_move_only(&mut self, mv: Move) -> bool {
@@ -131,6 +176,10 @@ impl Board {
                 updates.add(player, piece, to);
             }
 
+            if piece == King {
+                updates.move_king(player, from, to);
+            }
+
             if let Some(capture) = capture {
                 self.xor_piece(!player, capture, to);
                 updates.sub(!player, capture, to);
@@ -151,18 +200,19 @@ impl Board {
             self.pawn_hash ^= zobrist_piece(!player, Pawn, target.first_square());
         } else if mv.is_castling() {
             // find the destination square
-            const DESTS: [[(Square, Square); 2]; 2] = [
-                [(Square::C1, Square::D1), (Square::G1, Square::F1)],
-                [(Square::C8, Square::D8), (Square::G8, Square::F8)],
-            ];
             let kingside = from.file() < to.file();
-            let dest = DESTS[player][kingside as usize];
+            let dest = CASTLE_DESTS[player][kingside as usize];
 
-            // move the king and rook
-            self.move_piece(player, King, from, dest.0);
+            // move the king and rook, in Chess960 either may already be on its destination
+            if from != dest.0 {
+                self.move_piece(player, King, from, dest.0);
+            }
             updates.sub(player, King, from);
             updates.add(player, King, dest.0);
-            self.move_piece(player, Rook, to, dest.1);
+            updates.move_king(player, from, dest.0);
+            if to != dest.1 {
+                self.move_piece(player, Rook, to, dest.1);
+            }
             updates.sub(player, Rook, to);
             updates.add(player, Rook, dest.1);
         }
@@ -256,8 +306,10 @@ impl Board {
     pub fn make_null_move_nnue(&mut self, t: &mut ThreadData, ply: usize) {
         self.make_null_move_only();
 
-        // copy accumulators to next ply
-        t.accumulators[ply + 1] = t.accumulators[ply]
+        // the next ply's accumulator is a copy of this one
+        let kings = [self.king_square(White), self.king_square(Black)];
+        t.accumulators[ply + 1].set_pending(MoveUpdates::new(), kings);
+        t.move_stack[ply] = None;
     }
 
     pub fn is_pseudolegal(&self, mv: Move) -> bool {
@@ -299,27 +351,19 @@ impl Board {
 
         // piece special cases
         match piece {
-            King => {
-                // castling
+            // castling
+            King if mv.is_castling() => {
                 let rights = self.castles[self.player];
-                if (to & (rights[0] | rights[1])).is_not_empty() {
-                    // get for full castling legality
-                    let kingside = (to & rights[1]).is_not_empty();
-                    let castle = rights[kingside as usize];
-
-                    let rook_from = castle.first_square();
-                    // clear between rook and king
-                    if (lookup_between(mv.from(), rook_from) & self.all_pieces()).is_empty() {
-                        const KING_TARGETS: [[Square; 2]; 2] =
-                            [[Square::C1, Square::C8], [Square::G1, Square::G8]];
-                        let target = KING_TARGETS[kingside as usize][self.player];
-                        let enemy_attacks = self.all_attacks(!self.player);
-                        // clear and safe between king and king target
-                        return (lookup_between(mv.from(), target)
-                            & (self.all_pieces() | enemy_attacks))
-                            .is_empty();
-                    }
+                // castling must target a rook with castling rights
+                if (to & (rights[0] | rights[1])).is_empty() {
+                    return false;
+                }
+                let enemy_attacks = self.all_attacks(!self.player);
+                // in check, castling is illegal
+                if (enemy_attacks & from).is_not_empty() {
+                    return false;
                 }
+                return self.castle_is_clear(mv.from(), mv.to(), enemy_attacks);
             }
             Pawn => {
                 // erroneous promotions
@@ -353,8 +397,9 @@ impl Board {
         (to & piece_attacks & !pieces).is_not_empty()
     }
 
-    pub fn refresh_accumulator(&self, acc: &mut Accumulator) {
-        let mut new = Accumulator::new();
+    pub fn refresh_accumulator(&self, net: &Network, acc: &mut Accumulator) {
+        let mut new = Accumulator::new(net);
+        let kings = [self.king_square(White), self.king_square(Black)];
 
         for color in [White, Black] {
             for piece in [Pawn, Knight, Bishop, Rook, Queen, King] {
@@ -362,23 +407,25 @@ impl Board {
                 for square in pieces {
                     // white-relative accumulator
                     add_in_place(
+                        net,
                         &mut new.white,
                         Feature {
                             color,
                             piece,
                             square,
                         }
-                        .index(White),
+                        .index(White, kings[White]),
                     );
                     // black-relative accumulator
                     add_in_place(
+                        net,
                         &mut new.black,
                         Feature {
                             color,
                             piece,
                             square,
                         }
-                        .index(Black),
+                        .index(Black, kings[Black]),
                     );
                 }
             }
@@ -393,3 +440,50 @@ impl Default for Board {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{rng::Rng, thread_data::ThreadData, tt::TT};
+
+    use super::Board;
+
+    #[test]
+    fn test_lazy_accumulator_updates() {
+        let tt = TT::new(1);
+        let mut rng = Rng::new(0xacc);
+
+        for _ in 0..20 {
+            let mut board = Board::new();
+            let mut t = ThreadData::new(&board, tt.slice());
+            let mut fresh = ThreadData::new(&board, tt.slice());
+            board.refresh_root_accumulator(&mut t);
+
+            for ply in 0..100 {
+                let moves = board.legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                if !board.in_check() && rng.below(10) == 0 {
+                    board.make_null_move_nnue(&mut t, ply);
+                } else {
+                    assert!(board.make_move_nnue(moves[rng.below(moves.len())], &mut t, ply));
+                }
+
+                // leave some plies unevaluated so updates pile up
+                if rng.below(3) == 0 {
+                    let eval = board.evaluate(&mut t, ply + 1);
+                    board.refresh_root_accumulator(&mut fresh);
+                    assert_eq!(eval, board.evaluate(&mut fresh, 0), "{}", board.fen());
+
+                    let persp = board.player;
+                    assert_eq!(
+                        t.accumulators[ply + 1].acc[persp],
+                        fresh.accumulators[0].acc[persp],
+                        "{}",
+                        board.fen()
+                    );
+                }
+            }
+        }
+    }
+}
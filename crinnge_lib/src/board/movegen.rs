@@ -5,7 +5,7 @@ use crate::{
 
 use crinnge_bitboards::*;
 
-use super::{lookups::*, Board};
+use super::{lookups::*, Board, CASTLE_DESTS};
 
 impl Board {
     pub fn generate_moves_into(&self, noisy: &mut MoveList, quiet: &mut MoveList) {
@@ -139,25 +139,37 @@ impl Board {
         }
         let castles = self.castles[self.player];
 
-        const KING_TARGETS: [[Square; 2]; 2] = [[Square::C1, Square::C8], [Square::G1, Square::G8]];
-        for (i, castle) in castles.iter().enumerate() {
+        for castle in castles {
             if castle.is_not_empty() {
                 let rook_from = castle.first_square();
-                // clear between rook and king
-                if (lookup_between(from, rook_from) & self.all_pieces()).is_empty() {
-                    let target = KING_TARGETS[i][self.player];
-                    // clear and safe between king and king target
-                    if (lookup_between(from, target) & (self.all_pieces() | enemy_attacks))
-                        .is_empty()
-                    {
-                        let mv = Move::new_castle(from, rook_from);
-                        quiet.push(mv);
-                    }
+                if self.castle_is_clear(from, rook_from, enemy_attacks) {
+                    let mv = Move::new_castle(from, rook_from);
+                    quiet.push(mv);
                 }
             }
         }
     }
 
+    /// Checks the paths of the king and rook are clear and the king doesn't pass through check.
+    /// This works for any king and rook files, the king's destination is checked when the move is made
+    pub fn castle_is_clear(
+        &self,
+        king_from: Square,
+        rook_from: Square,
+        enemy_attacks: BitBoard,
+    ) -> bool {
+        let kingside = rook_from.file() > king_from.file();
+        let (king_to, rook_to) = CASTLE_DESTS[self.player][kingside as usize];
+
+        // the castling king and rook can't block each other
+        let blockers = self.all_pieces() ^ king_from.bitboard() ^ rook_from.bitboard();
+        let king_path = lookup_between(king_from, king_to) | king_to.bitboard();
+        let rook_path = lookup_between(rook_from, rook_to) | rook_to.bitboard();
+
+        (blockers & (king_path | rook_path)).is_empty()
+            && (lookup_between(king_from, king_to) & enemy_attacks).is_empty()
+    }
+
     pub fn legal_moves(&self) -> Vec<Move> {
         let mut noisy = MoveList::new();
         let mut quiet = MoveList::new();
@@ -173,6 +185,25 @@ impl Board {
         legals
     }
 
+    pub fn perft(&self, depth: usize) -> usize {
+        if depth == 0 {
+            return 1;
+        }
+        let mut count = 0usize;
+        let [mut noisy, mut quiet] = [MoveList::new(); 2];
+
+        self.generate_moves_into(&mut noisy, &mut quiet);
+
+        for mv in noisy.iter_moves().chain(quiet.iter_moves()) {
+            let mut next = *self;
+            if next.make_move_only(*mv) {
+                count += next.perft(depth - 1)
+            }
+        }
+
+        count
+    }
+
     pub fn pseudolegal_moves(&self) -> Vec<Move> {
         let mut noisy = MoveList::new();
         let mut quiet = MoveList::new();
@@ -186,3 +217,77 @@ impl Board {
         legals
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+
+    #[test]
+    fn test_perft() {
+        let test_cases = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                &[20, 400, 8902][..],
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                &[48, 2039, 97862],
+            ),
+        ];
+
+        for (fen, counts) in test_cases {
+            let board = Board::from_fen(fen).unwrap();
+            for (depth, &count) in counts.iter().enumerate() {
+                assert_eq!(board.perft(depth + 1), count, "{fen} depth {}", depth + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_perft_960() {
+        let test_cases = [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                &[21, 528, 12189][..],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                &[21, 807, 18002],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                &[20, 479, 10471],
+            ),
+            (
+                "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+                &[22, 593, 13440],
+            ),
+            (
+                "1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9",
+                &[28, 1120, 31058],
+            ),
+            // X-FEN castling rights
+            (
+                "r1k1r2q/p1ppp1pp/8/8/8/8/P1PPP1PP/R1K1R2Q w KQkq - 0 1",
+                &[23, 522, 12333],
+            ),
+            (
+                "r1k2r1q/p1ppp1pp/8/8/8/8/P1PPP1PP/R1K2R1Q w KQkq - 0 1",
+                &[28, 738, 20218],
+            ),
+            (
+                "8/8/8/4B2b/6nN/8/5P2/2R1K2k w Q - 0 1",
+                &[34, 318, 9002, 118388],
+            ),
+            ("2r5/8/8/8/8/8/6PP/k2KR3 w K - 0 1", &[17, 242, 3931, 57700]),
+            ("4r3/3k4/8/8/8/8/6PP/qR1K1R2 w KQ - 0 1", &[19, 628, 12858]),
+        ];
+
+        for (fen, counts) in test_cases {
+            let board = Board::from_fen(fen).unwrap();
+            for (depth, &count) in counts.iter().enumerate() {
+                assert_eq!(board.perft(depth + 1), count, "{fen} depth {}", depth + 1);
+            }
+        }
+    }
+}
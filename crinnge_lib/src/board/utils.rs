@@ -69,6 +69,10 @@ impl Board {
         }
         attacks
     }
+    pub fn king_square(&self, color: Color) -> Square {
+        self.kings[color].first_square()
+    }
+
     pub fn king_attacks(&self, color: Color) -> BitBoard {
         lookup_king_moves(self.kings[color].first_square())
     }
@@ -160,6 +164,14 @@ impl Board {
         self.halfmove_clock
     }
 
+    pub fn fullmove_count(&self) -> u16 {
+        self.fullmove_count
+    }
+
+    pub fn ep_mask(&self) -> BitBoard {
+        self.ep_mask
+    }
+
     pub fn hash(&self) -> u64 {
         self.hash
     }
@@ -172,6 +184,44 @@ impl Board {
         self.castles
     }
 
+    /// Build a board from the piece bitboards as ordered in `contents` and the rest of the state
+    pub fn from_parts(
+        pieces: [[BitBoard; 2]; 6],
+        player: Color,
+        castles: [[BitBoard; 2]; 2],
+        ep_mask: BitBoard,
+        halfmove_clock: u8,
+        fullmove_count: u16,
+    ) -> Self {
+        let [pawns, knights, bishops, rooks, queens, kings] = pieces;
+        let occupied = [White, Black].map(|color| {
+            pieces
+                .iter()
+                .fold(BitBoard::empty(), |occ, bbs| occ | bbs[color])
+        });
+
+        let mut board = Self {
+            pawns,
+            knights,
+            bishops,
+            rooks,
+            queens,
+            kings,
+            occupied,
+            castles,
+            player,
+            ep_mask,
+            halfmove_clock,
+            fullmove_count,
+            hash: 0,
+            pawn_hash: 0,
+        };
+        board.hash = board.recalculate_hash();
+        board.pawn_hash = board.recalculate_pawn_hash();
+
+        board
+    }
+
     pub fn in_check(&self) -> bool {
         (self.all_attacks(!self.player) & self.kings[self.player]).is_not_empty()
     }
@@ -0,0 +1,211 @@
+use std::io::{self, Read, Write};
+
+use crinnge_bitboards::{BitBoard, Square};
+
+use crate::{board::Board, types::*};
+
+use super::{invalid_data, pack_nibbles, pack_result, read_record, unpack_nibble};
+
+/// A position in bulletformat's 32 byte `ChessBoard` layout, from the side to move's point of view:
+/// positions with black to move are flipped vertically with the colours swapped, and the score
+/// and result are relative to the side to move
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChessBoard {
+    occ: u64,
+    /// Colour in bit 3 and piece in bits 0-2 for each piece in `occ`, lowest square first
+    pcs: [u8; 16],
+    score: i16,
+    result: u8,
+    ksq: u8,
+    /// The opponent's king square from their own point of view
+    opp_ksq: u8,
+    extra: [u8; 3],
+}
+
+impl ChessBoard {
+    pub const SIZE: usize = 32;
+
+    /// Pack `board` with `score` and `result` from white's point of view, or None if it has more
+    /// than 32 pieces or is missing a king
+    pub fn from_board(board: &Board, score: i16, result: f32) -> Option<Self> {
+        let (bbs, player) = board.contents();
+        let flip = player == Black;
+        let relative = |bb: BitBoard| if flip { bb.0.swap_bytes() } else { bb.0 };
+
+        let ours = relative(bbs[6][player]);
+        let theirs = relative(bbs[6][!player]);
+        let occ = ours | theirs;
+        if occ.count_ones() > 32 || bbs[5].iter().any(|kings| kings.count_ones() != 1) {
+            return None;
+        }
+
+        let pcs = pack_nibbles(BitBoard(occ), |sq| {
+            let bit = sq.bitboard().0;
+            let color = (theirs & bit != 0) as u8;
+            let piece = (0..6)
+                .find(|&p| relative(bbs[p][White] | bbs[p][Black]) & bit != 0)
+                .unwrap() as u8;
+            (color << 3) | piece
+        });
+
+        let (score, result) = if flip {
+            (score.saturating_neg(), 1.0 - result)
+        } else {
+            (score, result)
+        };
+
+        Some(Self {
+            occ,
+            pcs,
+            score,
+            result: pack_result(result),
+            ksq: relative(bbs[5][player]).trailing_zeros() as u8,
+            opp_ksq: relative(bbs[5][!player]).trailing_zeros() as u8 ^ 56,
+            extra: [0; 3],
+        })
+    }
+
+    /// Score for the side to move
+    pub fn score(&self) -> i16 {
+        self.score
+    }
+
+    /// Result for the side to move
+    pub fn result(&self) -> f32 {
+        f32::from(self.result) / 2.0
+    }
+
+    /// The pieces on the board, where white is the side to move
+    pub fn pieces(&self) -> impl Iterator<Item = (Color, Piece, Square)> + '_ {
+        BitBoard(self.occ).enumerate().map(|(i, sq)| {
+            let code = unpack_nibble(&self.pcs, i);
+            let color = if code & 8 == 0 { White } else { Black };
+            (color, Piece::from(code & 7), sq)
+        })
+    }
+
+    /// King squares of the side to move and the opponent, each from their own point of view
+    pub fn king_squares(&self) -> [Square; 2] {
+        [Square::from(self.ksq), Square::from(self.opp_ksq)]
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.occ.to_le_bytes());
+        bytes[8..24].copy_from_slice(&self.pcs);
+        bytes[24..26].copy_from_slice(&self.score.to_le_bytes());
+        bytes[26] = self.result;
+        bytes[27] = self.ksq;
+        bytes[28] = self.opp_ksq;
+        bytes[29..32].copy_from_slice(&self.extra);
+        bytes
+    }
+
+    /// Unpack a record, or None if it is malformed
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let board = Self {
+            occ: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            pcs: bytes[8..24].try_into().unwrap(),
+            score: i16::from_le_bytes([bytes[24], bytes[25]]),
+            result: bytes[26],
+            ksq: bytes[27],
+            opp_ksq: bytes[28],
+            extra: [bytes[29], bytes[30], bytes[31]],
+        };
+
+        let valid = board.occ.count_ones() <= 32
+            && board.result <= 2
+            && board.ksq < 64
+            && board.opp_ksq < 64
+            && (0..board.occ.count_ones() as usize).all(|i| unpack_nibble(&board.pcs, i) & 7 < 6);
+
+        valid.then_some(board)
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// Read the next record, returning None at the end of input
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let Some(bytes) = read_record::<{ Self::SIZE }>(reader)? else {
+            return Ok(None);
+        };
+        Self::from_bytes(&bytes)
+            .map(Some)
+            .ok_or_else(|| invalid_data("malformed ChessBoard record"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crinnge_bitboards::Square;
+
+    use crate::{board::Board, types::*};
+
+    use super::ChessBoard;
+
+    #[test]
+    fn test_chessboard_round_trip() {
+        let positions = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                25,
+                0.5,
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                -40,
+                1.0,
+            ),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1", 150, 0.0),
+            (
+                "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+                -700,
+                1.0,
+            ),
+        ];
+
+        for (fen, score, result) in positions {
+            let board = Board::from_fen(fen).unwrap();
+            let packed = ChessBoard::from_board(&board, score, result).unwrap();
+
+            let mut bytes = vec![];
+            packed.write_to(&mut bytes).unwrap();
+            assert_eq!(bytes.len(), ChessBoard::SIZE);
+            let mut reader = bytes.as_slice();
+            let read = ChessBoard::read_from(&mut reader).unwrap().unwrap();
+            assert_eq!(read, packed, "{fen}");
+            assert!(ChessBoard::read_from(&mut reader).unwrap().is_none());
+
+            // everything is relative to the side to move
+            let stm = board.player();
+            let relative = |sq: Square| if stm == Black { sq.flip() } else { sq };
+            let expected_score = if stm == White { score } else { -score };
+            let expected_result = if stm == White { result } else { 1.0 - result };
+            assert_eq!(read.score(), expected_score, "{fen}");
+            assert_eq!(read.result(), expected_result, "{fen}");
+            assert_eq!(
+                read.king_squares(),
+                [
+                    relative(board.king_square(stm)),
+                    relative(board.king_square(!stm)).flip()
+                ],
+                "{fen}"
+            );
+
+            let mut pieces = 0;
+            for (color, piece, sq) in read.pieces() {
+                let color = if color == White { stm } else { !stm };
+                let pieces_bb = board.pieces(piece)[color];
+                assert!(
+                    (pieces_bb & relative(sq).bitboard()).is_not_empty(),
+                    "{fen}: {color:?} {piece:?} on {}",
+                    relative(sq).coord()
+                );
+                pieces += 1;
+            }
+            assert_eq!(pieces, board.all_pieces().count_ones(), "{fen}");
+        }
+    }
+}
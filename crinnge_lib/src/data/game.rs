@@ -0,0 +1,329 @@
+use std::io::{self, Read, Write};
+
+use crinnge_bitboards::{BitBoard, Square};
+
+use crate::{board::Board, moves::Move, types::*};
+
+use super::{invalid_data, pack_nibbles, pack_result, read_record, unpack_nibble};
+
+/// Piece code for a rook that can still castle
+const CASTLING_ROOK: u8 = 6;
+/// En passant square code when there is no en passant square
+const NO_EP: u8 = 64;
+
+/// A complete position in 32 bytes, with the score and result from white's point of view
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackedBoard {
+    occ: u64,
+    /// Colour in bit 3 and piece in bits 0-2 for each piece in `occ`, lowest square first.
+    /// Rooks with castling rights use `CASTLING_ROOK` as their piece
+    pcs: [u8; 16],
+    /// Black to move in bit 7 and the en passant square in bits 0-6
+    stm_ep: u8,
+    halfmove_clock: u8,
+    fullmove_count: u16,
+    score: i16,
+    result: u8,
+    extra: u8,
+}
+
+impl PackedBoard {
+    pub const SIZE: usize = 32;
+
+    /// Pack `board`, or None if it has more than 32 pieces
+    pub fn from_board(board: &Board, score: i16, result: f32) -> Option<Self> {
+        let (bbs, player) = board.contents();
+        let occ = bbs[6][White] | bbs[6][Black];
+        if occ.count_ones() > 32 {
+            return None;
+        }
+
+        let castling_rooks = board
+            .castles()
+            .iter()
+            .flatten()
+            .fold(BitBoard::empty(), |rooks, &rook| rooks | rook);
+        let pcs = pack_nibbles(occ, |sq| {
+            let bit = sq.bitboard();
+            let color = (bbs[6][Black] & bit).is_not_empty() as u8;
+            let piece = if (castling_rooks & bit).is_not_empty() {
+                CASTLING_ROOK
+            } else {
+                (0..6)
+                    .find(|&p| ((bbs[p][White] | bbs[p][Black]) & bit).is_not_empty())
+                    .unwrap() as u8
+            };
+            (color << 3) | piece
+        });
+
+        let ep = board.ep_mask();
+        let ep = if ep.is_empty() {
+            NO_EP
+        } else {
+            *ep.first_square()
+        };
+
+        Some(Self {
+            occ: occ.0,
+            pcs,
+            stm_ep: ((player as u8) << 7) | ep,
+            halfmove_clock: board.halfmove_clock(),
+            fullmove_count: board.fullmove_count(),
+            score,
+            result: pack_result(result),
+            extra: 0,
+        })
+    }
+
+    /// Unpack the position, or None if it is malformed
+    pub fn to_board(&self) -> Option<Board> {
+        let mut pieces = [[BitBoard::empty(); 2]; 6];
+        let mut castling_rooks = [BitBoard::empty(); 2];
+
+        for (i, sq) in BitBoard(self.occ).enumerate() {
+            let code = unpack_nibble(&self.pcs, i);
+            let color = if code & 8 == 0 { White } else { Black };
+            match code & 7 {
+                CASTLING_ROOK => {
+                    pieces[Rook][color] |= sq.bitboard();
+                    castling_rooks[color] |= sq.bitboard();
+                }
+                piece @ 0..=5 => pieces[piece as usize][color] |= sq.bitboard(),
+                _ => return None,
+            }
+        }
+
+        let mut castles = [[BitBoard::empty(); 2]; 2];
+        for color in [White, Black] {
+            let kings = pieces[King][color];
+            if kings.count_ones() != 1 {
+                return None;
+            }
+            for rook in castling_rooks[color] {
+                let kingside = rook.file() > kings.first_square().file();
+                castles[color][kingside as usize] = rook.bitboard();
+            }
+        }
+
+        let player = if self.stm_ep >> 7 == 0 { White } else { Black };
+        let ep_mask = match self.stm_ep & 0x7f {
+            NO_EP => BitBoard::empty(),
+            sq @ 0..=63 => Square::from(sq).bitboard(),
+            _ => return None,
+        };
+
+        Some(Board::from_parts(
+            pieces,
+            player,
+            castles,
+            ep_mask,
+            self.halfmove_clock,
+            self.fullmove_count,
+        ))
+    }
+
+    pub fn score(&self) -> i16 {
+        self.score
+    }
+
+    pub fn result(&self) -> f32 {
+        f32::from(self.result) / 2.0
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.occ.to_le_bytes());
+        bytes[8..24].copy_from_slice(&self.pcs);
+        bytes[24] = self.stm_ep;
+        bytes[25] = self.halfmove_clock;
+        bytes[26..28].copy_from_slice(&self.fullmove_count.to_le_bytes());
+        bytes[28..30].copy_from_slice(&self.score.to_le_bytes());
+        bytes[30] = self.result;
+        bytes[31] = self.extra;
+        bytes
+    }
+
+    /// Unpack a record, or None if it is malformed
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let board = Self {
+            occ: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            pcs: bytes[8..24].try_into().unwrap(),
+            stm_ep: bytes[24],
+            halfmove_clock: bytes[25],
+            fullmove_count: u16::from_le_bytes([bytes[26], bytes[27]]),
+            score: i16::from_le_bytes([bytes[28], bytes[29]]),
+            result: bytes[30],
+            extra: bytes[31],
+        };
+
+        let valid = board.occ.count_ones() <= 32 && board.result <= 2 && board.to_board().is_some();
+        valid.then_some(board)
+    }
+}
+
+/// A game stored as its start position followed by the moves played, each with the white
+/// relative score of the position it was played from. Only the result of the start position is
+/// used, so a game takes 32 bytes plus 4 bytes per position and a 4 byte terminator
+#[derive(Clone, Debug, PartialEq)]
+pub struct Game {
+    start: PackedBoard,
+    moves: Vec<(Move, i16)>,
+}
+
+impl Game {
+    /// A game from `start` with no moves and a drawn result, or None if `start` has more than
+    /// 32 pieces
+    pub fn new(start: &Board) -> Option<Self> {
+        Some(Self {
+            start: PackedBoard::from_board(start, 0, 0.5)?,
+            moves: vec![],
+        })
+    }
+
+    /// Record `mv` played from the current position, which has a white relative `score`
+    pub fn push(&mut self, mv: Move, score: i16) {
+        self.moves.push((mv, score));
+    }
+
+    pub fn set_result(&mut self, result: f32) {
+        self.start.result = pack_result(result);
+    }
+
+    pub fn result(&self) -> f32 {
+        self.start.result()
+    }
+
+    pub fn moves(&self) -> &[(Move, i16)] {
+        &self.moves
+    }
+
+    /// Every position a move was played from, with its score and the game result
+    pub fn positions(&self) -> impl Iterator<Item = (Board, i16, f32)> + '_ {
+        let mut board = self
+            .start
+            .to_board()
+            .expect("games are built from valid boards");
+        let result = self.result();
+
+        self.moves.iter().map(move |&(mv, score)| {
+            let position = board;
+            let legal = board.make_move_only(mv);
+            debug_assert!(legal, "illegal move {} in game", mv.coords());
+            (position, score, result)
+        })
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.start.to_bytes())?;
+        for &(mv, score) in self.moves.iter() {
+            writer.write_all(&mv.0.to_le_bytes())?;
+            writer.write_all(&score.to_le_bytes())?;
+        }
+        // null moves are never played, so a null move with no score ends the game
+        writer.write_all(&[0; 4])
+    }
+
+    /// Read the next game, returning None at the end of input. Moves are checked for legality
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let Some(bytes) = read_record::<{ PackedBoard::SIZE }>(reader)? else {
+            return Ok(None);
+        };
+        let start =
+            PackedBoard::from_bytes(&bytes).ok_or_else(|| invalid_data("malformed game start"))?;
+        let mut board = start.to_board().unwrap();
+        let mut moves = vec![];
+
+        loop {
+            let bytes =
+                read_record::<4>(reader)?.ok_or_else(|| invalid_data("unterminated game"))?;
+            let mv = Move(u16::from_le_bytes([bytes[0], bytes[1]]));
+            let score = i16::from_le_bytes([bytes[2], bytes[3]]);
+            if mv.is_null() {
+                break;
+            }
+            if !board.is_pseudolegal(mv) || !board.make_move_only(mv) {
+                return Err(invalid_data("illegal move in game"));
+            }
+            moves.push((mv, score));
+        }
+
+        Ok(Some(Self { start, moves }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+
+    use super::{Game, PackedBoard};
+
+    #[test]
+    fn test_packed_board_round_trip() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w Kq - 3 17",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 47 63",
+            // Chess960 with an inner rook behind the castling rook
+            "1r2k1rr/8/8/8/8/8/8/RR2K2R w HBgb - 0 1",
+        ];
+
+        for fen in fens {
+            let board = Board::from_fen(fen).unwrap();
+            let packed = PackedBoard::from_board(&board, -321, 1.0).unwrap();
+            let unpacked = PackedBoard::from_bytes(&packed.to_bytes())
+                .unwrap()
+                .to_board()
+                .unwrap();
+            assert_eq!(unpacked.shredder_fen(), board.shredder_fen());
+            assert_eq!(unpacked.hash(), board.hash(), "{fen}");
+            assert_eq!(packed.score(), -321);
+            assert_eq!(packed.result(), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_game_round_trip() {
+        let start =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+
+        // play the first legal move a few times
+        let mut game = Game::new(&start).unwrap();
+        let mut board = start;
+        let mut fens = vec![];
+        for score in [12, -5, 300, -2000, 0, 7] {
+            let mv = board.legal_moves()[0];
+            fens.push(board.fen());
+            game.push(mv, score);
+            assert!(board.make_move_only(mv));
+        }
+        game.set_result(0.0);
+
+        let mut bytes = vec![];
+        game.write_to(&mut bytes).unwrap();
+        game.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            bytes.len(),
+            2 * (PackedBoard::SIZE + 4 * game.moves().len() + 4)
+        );
+
+        let mut reader = bytes.as_slice();
+        for _ in 0..2 {
+            let read = Game::read_from(&mut reader).unwrap().unwrap();
+            assert_eq!(read, game);
+            let positions: Vec<_> = read
+                .positions()
+                .map(|(board, score, result)| (board.fen(), score, result))
+                .collect();
+            let expected: Vec<_> = fens
+                .iter()
+                .cloned()
+                .zip(game.moves().iter().map(|&(_, score)| score))
+                .map(|(fen, score)| (fen, score, 0.0))
+                .collect();
+            assert_eq!(positions, expected);
+        }
+        assert!(Game::read_from(&mut reader).unwrap().is_none());
+    }
+}
@@ -0,0 +1,55 @@
+//! Training data formats
+//!
+//! Scores and results are from white's point of view unless noted otherwise, matching the
+//! `fen | score | result` lines written by datagen. Results are 1.0 for a white win, 0.5 for a
+//! draw and 0.0 for a black win
+
+use std::io::{self, Read};
+
+use crinnge_bitboards::{BitBoard, Square};
+
+pub mod chessboard;
+pub mod game;
+pub mod text;
+
+pub use chessboard::ChessBoard;
+pub use game::{Game, PackedBoard};
+pub use text::{convert_text, parse_line};
+
+/// Result as stored in the binary formats, 0 for a loss, 1 for a draw and 2 for a win
+fn pack_result(result: f32) -> u8 {
+    (2.0 * result.clamp(0.0, 1.0)).round() as u8
+}
+
+/// Pieces as 4 bit codes in square order, two per byte with the lower square in the low nibble
+fn pack_nibbles(occ: BitBoard, mut code: impl FnMut(Square) -> u8) -> [u8; 16] {
+    let mut pcs = [0; 16];
+    for (i, sq) in occ.enumerate() {
+        pcs[i / 2] |= code(sq) << (4 * (i & 1));
+    }
+    pcs
+}
+
+fn unpack_nibble(pcs: &[u8; 16], i: usize) -> u8 {
+    (pcs[i / 2] >> (4 * (i & 1))) & 0xf
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Read one fixed size record, returning None at a clean end of input
+fn read_record<const N: usize>(reader: &mut impl Read) -> io::Result<Option<[u8; N]>> {
+    let mut bytes = [0; N];
+    let mut filled = 0;
+    while filled < N {
+        match reader.read(&mut bytes[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(invalid_data("truncated training data record")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(bytes))
+}
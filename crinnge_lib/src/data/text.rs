@@ -0,0 +1,88 @@
+use std::io::{self, BufRead, Write};
+
+use crate::board::Board;
+
+use super::{invalid_data, ChessBoard};
+
+/// Parse a `fen | score | result` line, with the score and result from white's point of view.
+/// Results may be written as 1.0/0.5/0.0 or 1-0/1/2-1/2/0-1, optionally in brackets
+pub fn parse_line(line: &str) -> Option<(Board, i16, f32)> {
+    let mut parts = line.split('|').map(str::trim);
+    let board = Board::from_fen(parts.next()?)?;
+    let score = parts.next()?.parse::<i32>().ok()?;
+    let result = match parts.next()?.trim_matches(['[', ']']) {
+        "1-0" => 1.0,
+        "1/2-1/2" => 0.5,
+        "0-1" => 0.0,
+        r => r.parse::<f32>().ok().filter(|r| (0.0..=1.0).contains(r))?,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let score = score.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    Some((board, score, result))
+}
+
+/// Convert `fen | score | result` lines to `ChessBoard` records, returning how many were written.
+/// Blank lines are skipped, any other line that does not parse is an error
+pub fn convert_text(input: impl BufRead, output: &mut impl Write) -> io::Result<usize> {
+    let mut count = 0;
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = parse_line(&line)
+            .and_then(|(board, score, result)| ChessBoard::from_board(&board, score, result))
+            .ok_or_else(|| invalid_data(&format!("invalid training data on line {}", i + 1)))?;
+        record.write_to(output)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::ChessBoard;
+
+    use super::{convert_text, parse_line};
+
+    #[test]
+    fn test_convert_text() {
+        let text = "\
+rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1 | 35 | 0.5
+8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1 | -120 | 0-1
+
+r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1 | 99999 | [1.0]
+";
+        let mut bytes = vec![];
+        assert_eq!(convert_text(text.as_bytes(), &mut bytes).unwrap(), 3);
+
+        let mut reader = bytes.as_slice();
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            let (board, score, result) = parse_line(line).unwrap();
+            let expected = ChessBoard::from_board(&board, score, result).unwrap();
+            assert_eq!(ChessBoard::read_from(&mut reader).unwrap(), Some(expected));
+        }
+        assert!(ChessBoard::read_from(&mut reader).unwrap().is_none());
+
+        // scores saturate and results are relative to the side to move
+        let (_, score, result) = parse_line(text.lines().nth(3).unwrap()).unwrap();
+        assert_eq!((score, result), (i16::MAX, 1.0));
+        let first = ChessBoard::read_from(&mut bytes.as_slice())
+            .unwrap()
+            .unwrap();
+        assert_eq!((first.score(), first.result()), (-35, 0.5));
+
+        assert!(
+            parse_line("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 0").is_none()
+        );
+        assert!(
+            parse_line("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 0 | 2")
+                .is_none()
+        );
+    }
+}
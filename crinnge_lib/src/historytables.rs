@@ -4,6 +4,12 @@ use crate::types::*;
 
 const HISTORY_MAX: i16 = i16::MAX / 2;
 
+const CORRECTION_HISTORY_SIZE: usize = 16384;
+/// Corrections are stored in fractions of a centipawn so that small updates aren't lost
+const CORRECTION_GRAIN: i32 = 256;
+const CORRECTION_WEIGHT_SCALE: i32 = 256;
+const CORRECTION_MAX: i32 = 64 * CORRECTION_GRAIN;
+
 #[derive(Copy, Clone, Debug)]
 pub struct HistoryTable([[i16; 64]; 6]);
 
@@ -35,6 +41,105 @@ impl Default for HistoryTable {
     }
 }
 
+/// History of captures, indexed by the moving piece, destination square and captured piece
+#[derive(Copy, Clone, Debug)]
+pub struct CaptureHistory([[[i16; 6]; 64]; 6]);
+
+impl CaptureHistory {
+    pub fn new() -> Self {
+        Self([[[0; 6]; 64]; 6])
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new()
+    }
+
+    pub fn get(&self, piece: Piece, to: Square, captured: Piece) -> i16 {
+        self.0[piece][to][captured]
+    }
+
+    pub fn get_mut(&mut self, piece: Piece, to: Square, captured: Piece) -> &mut i16 {
+        &mut self.0[piece][to][captured]
+    }
+}
+
+impl Default for CaptureHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Histories of quiet moves that follow an earlier move, indexed by the earlier move's piece and
+/// destination square
+#[derive(Clone, Debug)]
+pub struct ContinuationHistory(Box<[[HistoryTable; 64]; 6]>);
+
+impl ContinuationHistory {
+    pub fn new() -> Self {
+        Self(
+            vec![[HistoryTable::new(); 64]; 6]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.0.iter_mut().flatten().for_each(HistoryTable::clear);
+    }
+
+    pub fn get(&self, (piece, to): (Piece, Square)) -> &HistoryTable {
+        &self.0[piece][to]
+    }
+
+    pub fn get_mut(&mut self, (piece, to): (Piece, Square)) -> &mut HistoryTable {
+        &mut self.0[piece][to]
+    }
+}
+
+impl Default for ContinuationHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Learned differences between search scores and the static eval, indexed by side to move and a
+/// hash of some feature of the position
+#[derive(Clone, Debug)]
+pub struct CorrectionHistory(Box<[[i16; CORRECTION_HISTORY_SIZE]; 2]>);
+
+impl CorrectionHistory {
+    pub fn new() -> Self {
+        Self(Box::new([[0; CORRECTION_HISTORY_SIZE]; 2]))
+    }
+
+    pub fn clear(&mut self) {
+        self.0.iter_mut().for_each(|table| table.fill(0));
+    }
+
+    /// The correction to the static eval in centipawns
+    pub fn get(&self, player: Color, hash: u64) -> i32 {
+        self.0[player][hash as usize % CORRECTION_HISTORY_SIZE] as i32 / CORRECTION_GRAIN
+    }
+
+    /// Move the correction towards `diff`, trusting deeper searches more
+    pub fn update(&mut self, player: Color, hash: u64, depth: i32, diff: i32) {
+        let entry = &mut self.0[player][hash as usize % CORRECTION_HISTORY_SIZE];
+        let weight = (depth + 1).min(16);
+
+        let updated = (*entry as i32 * (CORRECTION_WEIGHT_SCALE - weight)
+            + diff * CORRECTION_GRAIN * weight)
+            / CORRECTION_WEIGHT_SCALE;
+        *entry = updated.clamp(-CORRECTION_MAX, CORRECTION_MAX) as i16;
+    }
+}
+
+impl Default for CorrectionHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn apply_history_bonus(score: &mut i16, delta: i16) {
     *score += (delta as i32 - (delta as i32 * *score as i32) / HISTORY_MAX as i32) as i16;
 }
@@ -8,6 +8,8 @@ pub mod timeman;
 pub mod tt;
 pub mod move_sorting;
 pub mod historytables;
+pub mod data;
+pub mod rng;
 
 pub use crinnge_bitboards::*;
 pub use crate::types::*;
\ No newline at end of file
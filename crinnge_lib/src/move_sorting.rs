@@ -12,12 +12,18 @@ const MVV_LVA: [[i16; 6]; 6] = [
     [0, 0, 0, 0, 0, 0],       // King capture (not possible)
 ];
 const BAD_NOISY: i16 = -10100;
+/// Capture history is scaled down so that it only breaks MVV-LVA ties and near ties
+const CAPTURE_HISTORY_ORDER_DIVISOR: i32 = 32;
+/// Captures with good history may lose a little material and still count as good
+const CAPTURE_HISTORY_SEE_DIVISOR: i32 = 64;
 
 #[derive(PartialEq, Eq)]
 pub enum MoveGenStage {
     TTMove,
     GenerateMoves,
     GoodNoisies,
+    Killers,
+    CounterMove,
     Quiets,
     BadNoisies,
 }
@@ -26,6 +32,10 @@ use MoveGenStage::*;
 
 pub struct MoveSorter<'a> {
     tt_move: Option<Move>,
+    ply: usize,
+    killers: [Move; 2],
+    killer_index: usize,
+    counter_move: Move,
     noisies: &'a mut MoveList,
     noisy_index: usize,
     quiets: &'a mut MoveList,
@@ -35,9 +45,18 @@ pub struct MoveSorter<'a> {
 }
 
 impl<'a> MoveSorter<'a> {
-    pub fn new(tt_move: Option<Move>, noisies: &'a mut MoveList, quiets: &'a mut MoveList) -> Self {
+    pub fn new(
+        tt_move: Option<Move>,
+        ply: usize,
+        noisies: &'a mut MoveList,
+        quiets: &'a mut MoveList,
+    ) -> Self {
         Self {
             tt_move,
+            ply,
+            killers: [Move::NULL; 2],
+            killer_index: 0,
+            counter_move: Move::NULL,
             noisies,
             noisy_index: 0,
             quiets,
@@ -52,6 +71,13 @@ impl<'a> MoveSorter<'a> {
         self
     }
 
+    /// Try these quiet moves before the other quiets if they are pseudolegal here
+    pub fn refutations(mut self, killers: [Move; 2], counter_move: Move) -> Self {
+        self.killers = killers;
+        self.counter_move = counter_move;
+        self
+    }
+
     pub fn next(&mut self, board: &Board, t: &ThreadData) -> Option<(Move, MoveGenStage)> {
         if self.stage == TTMove {
             self.stage = GenerateMoves;
@@ -91,8 +117,7 @@ impl<'a> MoveSorter<'a> {
                         // put this noisy back in the list
                         self.noisy_index -= 1;
                         if !self.noisy_only {
-                            self.stage = Quiets;
-                            self.score_quiets(board, t);
+                            self.stage = Killers;
                         } else {
                             self.stage = BadNoisies;
                         }
@@ -102,8 +127,7 @@ impl<'a> MoveSorter<'a> {
                     return Some((noisy.mv, GoodNoisies));
                 } else {
                     if !self.noisy_only {
-                        self.stage = Quiets;
-                        self.score_quiets(board, t);
+                        self.stage = Killers;
                     } else {
                         self.stage = BadNoisies;
                     }
@@ -112,17 +136,41 @@ impl<'a> MoveSorter<'a> {
             }
         }
 
+        if self.stage == Killers {
+            while let Some(&killer) = self.killers.get(self.killer_index) {
+                self.killer_index += 1;
+                if self.is_refutation(board, killer) {
+                    return Some((killer, Killers));
+                }
+            }
+            self.stage = CounterMove;
+        }
+
+        if self.stage == CounterMove {
+            self.stage = Quiets;
+            self.score_quiets(board, t);
+            let mv = self.counter_move;
+            if !self.killers.contains(&mv) && self.is_refutation(board, mv) {
+                return Some((mv, CounterMove));
+            }
+        }
+
         if self.stage == Quiets {
             loop {
                 let quiet = self.quiets.next(self.quiet_index);
                 self.quiet_index += 1;
-                if quiet.is_none() {
+                let Some(quiet) = quiet else {
                     self.stage = BadNoisies;
                     break;
-                } else if quiet.map(|e| e.mv) == self.tt_move {
+                };
+                // don't repeat the TT move or refutations
+                if Some(quiet.mv) == self.tt_move
+                    || self.killers.contains(&quiet.mv)
+                    || quiet.mv == self.counter_move
+                {
                     continue;
                 }
-                return Some((quiet.unwrap().mv, Quiets));
+                return Some((quiet.mv, Quiets));
             }
         }
 
@@ -143,15 +191,28 @@ impl<'a> MoveSorter<'a> {
         None
     }
 
-    fn score_noisies(&mut self, board: &Board, _t: &ThreadData) {
+    /// Whether a stored refutation can be played here as a quiet, without repeating the TT move.
+    /// Refutations come from other positions so they may not even be pseudolegal
+    fn is_refutation(&self, board: &Board, mv: Move) -> bool {
+        Some(mv) != self.tt_move
+            && board.is_pseudolegal(mv)
+            && !board.is_capture(mv)
+            && mv.promo().is_none()
+    }
+
+    fn score_noisies(&mut self, board: &Board, t: &ThreadData) {
         for noisy in self.noisies.iter_mut() {
             let piece = board.piece_on(noisy.mv.from()).unwrap();
             let capture = board.piece_on(noisy.mv.to()).unwrap_or(Pawn); // promotions may not have a capture
-            let mvv_lva = MVV_LVA[capture][piece];
+            let mvv_lva = MVV_LVA[capture][piece] as i32;
+            let history = t.capture_history.get(piece, noisy.mv.to(), capture) as i32;
 
-            let bad = !board.see_beats_threshold(noisy.mv, 0);
+            let threshold = -history / CAPTURE_HISTORY_SEE_DIVISOR;
+            let bad = !board.see_beats_threshold(noisy.mv, threshold as i16);
 
-            noisy.score = BAD_NOISY * bad as i16 + mvv_lva;
+            // MVV-LVA is scaled so that good noisies stay positive whatever their history
+            let order = 64 * mvv_lva + history / CAPTURE_HISTORY_ORDER_DIVISOR;
+            noisy.score = BAD_NOISY * bad as i16 + order as i16;
         }
     }
 
@@ -159,7 +220,8 @@ impl<'a> MoveSorter<'a> {
         for quiet in self.quiets.iter_mut() {
             let piece = board.piece_on(quiet.mv.from()).unwrap();
 
-            quiet.score = t.history.get(piece, quiet.mv.to());
+            // the sum of three histories can overflow the move score
+            quiet.score = (t.quiet_history(self.ply, piece, quiet.mv.to()) / 2) as i16;
         }
     }
 }
@@ -1,6 +1,7 @@
 use std::{
     fmt::Display,
     ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::{board::Board, types::*};
@@ -10,6 +11,10 @@ const FLAGS_MASK: u16 = 0b1100_0000_0000_0000;
 const PROMO_FLAG: u16 = FLAGS_MASK;
 const CASTLE_FLAG: u16 = 0b1000_0000_0000_0000;
 const EP_FLAG: u16 = 0b0100_0000_0000_0000;
+
+/// UCI_Chess960: print castling moves as king-takes-rook instead of a two-square king move
+pub static CHESS960: AtomicBool = AtomicBool::new(false);
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct Move(pub u16);
 
@@ -61,11 +66,11 @@ impl Move {
         self.0 == 0
     }
     pub fn coords(&self) -> String {
-        if self.is_castling() {
-            let black = self.from().rank() == 7;
+        if self.is_castling() && !CHESS960.load(Ordering::Relaxed) {
+            // standard notation moves the king to the c or g file
             let kingside = self.to().file() > self.from().file();
-            const CASTLES: [[&str; 2]; 2] = [["e1c1", "e1g1"], ["e8c8", "e8g8"]];
-            return CASTLES[black as usize][kingside as usize].to_string();
+            let target = Square::from(self.from().rank() * 8 + if kingside { 6 } else { 2 });
+            return format!("{}{}", self.from().coord(), target.coord());
         }
         let from = self.from().coord();
         let to = self.to().coord();
@@ -86,7 +91,7 @@ impl Move {
     pub fn from_pair<T: AsRef<str>>(board: &Board, pair: T) -> Self {
         let pair = pair.as_ref();
         let from = Square::from_coord(&pair[0..2]);
-        let mut to = Square::from_coord(&pair[2..4]);
+        let to = Square::from_coord(&pair[2..4]);
         let promo = match pair.chars().nth(4) {
             Some('n') => Some(Knight),
             Some('b') => Some(Bishop),
@@ -97,13 +102,14 @@ impl Move {
 
         let piece = board.piece_on(from).unwrap_or(Pawn);
         if piece == King {
-            // correct for castling
-            if to.file() > from.file() && to.file().abs_diff(from.file()) > 1 {
-                // kingside castling
-                to = board.castles()[board.player()][0].first_square()
-            } else if to.file() < from.file() && to.file().abs_diff(from.file()) > 1 {
-                // queenside castling
-                to = board.castles()[board.player()][1].first_square()
+            let rights = board.castles()[board.player()];
+            if (to.bitboard() & (rights[0] | rights[1])).is_not_empty() {
+                // king-takes-rook castling
+                return Self::new_castle(from, to);
+            } else if to.file().abs_diff(from.file()) > 1 {
+                // two-square king move castling
+                let kingside = to.file() > from.file();
+                return Self::new_castle(from, rights[kingside as usize].first_square());
             }
         }
 
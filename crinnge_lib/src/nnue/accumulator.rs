@@ -1,8 +1,13 @@
+use std::ops::{Index, IndexMut};
+
 use crinnge_bitboards::*;
 
 use crate::types::*;
 
-use super::{feature::Feature, Aligned, NNUE};
+use super::{
+    feature::{needs_refresh, Feature},
+    simd, Aligned, Network,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Accumulator {
@@ -11,136 +16,144 @@ pub struct Accumulator {
 }
 
 impl Accumulator {
-    pub fn new() -> Self {
+    /// An accumulator with no features active in `net`
+    pub fn new(net: &Network) -> Self {
         Self {
-            white: NNUE.feature_bias,
-            black: NNUE.feature_bias,
+            white: net.feature_bias,
+            black: net.feature_bias,
         }
     }
+}
 
-    pub fn apply_into(&self, dst: &mut Accumulator, updates: MoveUpdates) {
-        let white_add1_index = updates.adds[0].index(White);
-        let white_sub1_index = updates.subs[0].index(White);
-        let black_add1_index = updates.adds[0].index(Black);
-        let black_sub1_index = updates.subs[0].index(Black);
-
-        match (updates.nadds, updates.nsubs) {
-            (1, 1) => {
-                add_sub_into(
-                    &self.white,
-                    &mut dst.white,
-                    white_add1_index,
-                    white_sub1_index,
-                );
-                add_sub_into(
-                    &self.black,
-                    &mut dst.black,
-                    black_add1_index,
-                    black_sub1_index,
-                );
-            }
-            (1, 2) => {
-                let white_sub2_index = updates.subs[1].index(White);
-                let black_sub2_index = updates.subs[1].index(Black);
-                add_sub2_into(
-                    &self.white,
-                    &mut dst.white,
-                    white_add1_index,
-                    [white_sub1_index, white_sub2_index],
-                );
-                add_sub2_into(
-                    &self.black,
-                    &mut dst.black,
-                    black_add1_index,
-                    [black_sub1_index, black_sub2_index],
-                );
-            }
-            (2, 2) => {
-                let white_add2_index = updates.adds[1].index(White);
-                let white_sub2_index = updates.subs[1].index(White);
-                let black_add2_index = updates.adds[1].index(Black);
-                let black_sub2_index = updates.subs[1].index(Black);
-                add2_sub2_into(
-                    &self.white,
-                    &mut dst.white,
-                    [white_add1_index, white_add2_index],
-                    [white_sub1_index, white_sub2_index],
-                );
-                add2_sub2_into(
-                    &self.black,
-                    &mut dst.black,
-                    [black_add1_index, black_add2_index],
-                    [black_sub1_index, black_sub2_index],
-                );
-            }
-            _ => unreachable!(),
+impl Index<Color> for Accumulator {
+    type Output = Aligned;
+
+    fn index(&self, index: Color) -> &Self::Output {
+        match index {
+            White => &self.white,
+            Black => &self.black,
         }
     }
 }
 
-impl Default for Accumulator {
-    fn default() -> Self {
-        Self::new()
+impl IndexMut<Color> for Accumulator {
+    fn index_mut(&mut self, index: Color) -> &mut Self::Output {
+        match index {
+            White => &mut self.white,
+            Black => &mut self.black,
+        }
     }
 }
 
-pub fn add_in_place(acc: &mut Aligned, index: usize) {
-    let weights = NNUE.feature_weights[index];
+/// An accumulator on the per-ply stack. Making a move only records its updates, which are applied
+/// when the accumulator is first needed so that pruned nodes skip the NNUE work
+#[derive(Clone, Copy, Debug)]
+pub struct AccumulatorEntry {
+    pub acc: Accumulator,
+    pub updates: MoveUpdates,
+    /// King squares of the position, indexed by perspective
+    pub kings: [Square; 2],
+    /// Whether each perspective of `acc` is up to date
+    pub computed: [bool; 2],
+}
 
-    for (v, w) in acc.iter_mut().zip(weights.iter()) {
-        *v += w
+impl AccumulatorEntry {
+    pub fn new(net: &Network) -> Self {
+        Self {
+            acc: Accumulator::new(net),
+            updates: MoveUpdates::new(),
+            kings: [Square::NULL; 2],
+            computed: [true; 2],
+        }
+    }
+
+    /// Record the updates of the move leading to this entry, to be applied later
+    pub fn set_pending(&mut self, updates: MoveUpdates, kings: [Square; 2]) {
+        self.updates = updates;
+        self.kings = kings;
+        self.computed = [false; 2];
     }
 }
 
-pub fn sub_in_place(acc: &mut Aligned, index: usize) {
-    let weights = NNUE.feature_weights[index];
+/// Apply a move's feature updates to one side of an accumulator, with that side's king on `king`
+pub fn apply_updates_into(
+    net: &Network,
+    src: &Aligned,
+    dst: &mut Aligned,
+    updates: MoveUpdates,
+    persp: Color,
+    king: Square,
+) {
+    // null moves change nothing
+    if updates.nadds == 0 {
+        *dst = *src;
+        return;
+    }
+
+    let add1 = updates.adds[0].index(persp, king);
+    let sub1 = updates.subs[0].index(persp, king);
 
-    for (v, w) in acc.iter_mut().zip(weights.iter()) {
-        *v -= w
+    match (updates.nadds, updates.nsubs) {
+        (1, 1) => add_sub_into(net, src, dst, add1, sub1),
+        (1, 2) => {
+            let sub2 = updates.subs[1].index(persp, king);
+            add_sub2_into(net, src, dst, add1, [sub1, sub2]);
+        }
+        (2, 2) => {
+            let add2 = updates.adds[1].index(persp, king);
+            let sub2 = updates.subs[1].index(persp, king);
+            add2_sub2_into(net, src, dst, [add1, add2], [sub1, sub2]);
+        }
+        _ => unreachable!(),
     }
 }
 
-pub fn add_sub_into(src: &Aligned, dst: &mut Aligned, add: usize, sub: usize) {
-    let add_weights = NNUE.feature_weights[add];
-    let sub_weights = NNUE.feature_weights[sub];
+pub fn add_in_place(net: &Network, acc: &mut Aligned, index: usize) {
+    simd::add(acc, &net.feature_weights[index]);
+}
 
-    for ((src, dst), (add, sub)) in src
-        .iter()
-        .zip(dst.iter_mut())
-        .zip(add_weights.iter().zip(sub_weights.iter()))
-    {
-        *dst = src + add - sub
-    }
+pub fn sub_in_place(net: &Network, acc: &mut Aligned, index: usize) {
+    simd::sub(acc, &net.feature_weights[index]);
 }
 
-pub fn add_sub2_into(src: &Aligned, dst: &mut Aligned, add: usize, subs: [usize; 2]) {
-    let add_weights = NNUE.feature_weights[add];
-    let sub1_weights = NNUE.feature_weights[subs[0]];
-    let sub2_weights = NNUE.feature_weights[subs[1]];
-
-    for ((src, dst), (add, (sub1, sub2))) in src.iter().zip(dst.iter_mut()).zip(
-        add_weights
-            .iter()
-            .zip(sub1_weights.iter().zip(sub2_weights.iter())),
-    ) {
-        *dst = src + add - sub1 - sub2;
-    }
+pub fn add_sub_into(net: &Network, src: &Aligned, dst: &mut Aligned, add: usize, sub: usize) {
+    simd::add_sub(
+        src,
+        dst,
+        &net.feature_weights[add],
+        &net.feature_weights[sub],
+    );
 }
 
-pub fn add2_sub2_into(src: &Aligned, dst: &mut Aligned, adds: [usize; 2], subs: [usize; 2]) {
-    let add1_weights = NNUE.feature_weights[adds[0]];
-    let add2_weights = NNUE.feature_weights[adds[1]];
-    let sub1_weights = NNUE.feature_weights[subs[0]];
-    let sub2_weights = NNUE.feature_weights[subs[1]];
-
-    for ((src, dst), ((add1, add2), (sub1, sub2))) in src.iter().zip(dst.iter_mut()).zip(
-        add1_weights
-            .iter()
-            .zip(add2_weights.iter())
-            .zip(sub1_weights.iter().zip(sub2_weights.iter())),
-    ) {
-        *dst = src + add1 + add2 - sub1 - sub2;
-    }
+pub fn add_sub2_into(
+    net: &Network,
+    src: &Aligned,
+    dst: &mut Aligned,
+    add: usize,
+    subs: [usize; 2],
+) {
+    simd::add_sub2(
+        src,
+        dst,
+        &net.feature_weights[add],
+        &net.feature_weights[subs[0]],
+        &net.feature_weights[subs[1]],
+    );
+}
+
+pub fn add2_sub2_into(
+    net: &Network,
+    src: &Aligned,
+    dst: &mut Aligned,
+    adds: [usize; 2],
+    subs: [usize; 2],
+) {
+    simd::add2_sub2(
+        src,
+        dst,
+        [&net.feature_weights[adds[0]], &net.feature_weights[adds[1]]],
+        [&net.feature_weights[subs[0]], &net.feature_weights[subs[1]]],
+    );
 }
 
 #[derive(Copy, Clone, Debug, Default)]
@@ -149,6 +162,7 @@ pub struct MoveUpdates {
     subs: [Feature; 2],
     nadds: usize,
     nsubs: usize,
+    refresh: [bool; 2],
 }
 
 impl MoveUpdates {
@@ -171,4 +185,11 @@ impl MoveUpdates {
         };
         self.nsubs += 1;
     }
+    /// Record a king move, which may need its side's accumulator to be refreshed
+    pub fn move_king(&mut self, color: Color, from: Square, to: Square) {
+        self.refresh[color] = needs_refresh(color, from, to);
+    }
+    pub fn needs_refresh(&self, persp: Color) -> bool {
+        self.refresh[persp]
+    }
 }
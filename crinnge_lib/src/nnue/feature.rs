@@ -1,6 +1,8 @@
 use crate::types::*;
 use crinnge_bitboards::*;
 
+use super::{INPUT_SIZE, KING_BUCKETS, MIRRORED};
+
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct Feature {
     pub color: Color,
@@ -9,17 +11,70 @@ pub struct Feature {
 }
 
 impl Feature {
-    pub fn index(&self, persp: Color) -> usize {
+    /// Input index of the feature for the `persp` accumulator, with that side's king on `king`
+    pub fn index(&self, persp: Color, king: Square) -> usize {
+        LAYOUT.index(*self, persp, king)
+    }
+}
+
+/// How a side's king square selects the input bucket and mirroring of its accumulator
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InputLayout {
+    pub king_buckets: [u8; 64],
+    pub mirrored: bool,
+}
+
+/// The input layout of the network
+pub const LAYOUT: InputLayout = InputLayout {
+    king_buckets: KING_BUCKETS,
+    mirrored: MIRRORED,
+};
+
+impl InputLayout {
+    /// Input bucket and horizontal mirroring of the `persp` accumulator with that side's king on `king`
+    #[inline(always)]
+    pub fn bucket(&self, persp: Color, king: Square) -> (usize, bool) {
+        let mut king = if persp == White { king } else { king.flip() };
+        let mirrored = self.mirrored && king.file() >= 4;
+        if mirrored {
+            king = king.mirror();
+        }
+
+        (self.king_buckets[king] as usize, mirrored)
+    }
+
+    /// Input index of `feature` for the `persp` accumulator, with that side's king on `king`
+    #[inline(always)]
+    pub fn index(&self, feature: Feature, persp: Color, king: Square) -> usize {
         const PIECE_OFFSET: usize = 64;
         const COLOR_OFFSET: usize = PIECE_OFFSET * 6;
 
-        let ntm = persp != self.color;
-        let square = if persp == White {
-            self.square
+        let ntm = persp != feature.color;
+        let mut square = if persp == White {
+            feature.square
         } else {
-            self.square.flip()
+            feature.square.flip()
         };
+        let (bucket, mirrored) = self.bucket(persp, king);
+        if mirrored {
+            square = square.mirror();
+        }
 
-        ntm as usize * COLOR_OFFSET + self.piece as usize * PIECE_OFFSET + *square as usize
+        bucket * INPUT_SIZE
+            + ntm as usize * COLOR_OFFSET
+            + feature.piece as usize * PIECE_OFFSET
+            + *square as usize
     }
 }
+
+/// Input bucket and horizontal mirroring of the `persp` accumulator with that side's king on `king`
+#[inline(always)]
+pub fn bucket(persp: Color, king: Square) -> (usize, bool) {
+    LAYOUT.bucket(persp, king)
+}
+
+/// Whether a king move changes the layout of its side's accumulator, so it can't be updated incrementally
+#[inline(always)]
+pub fn needs_refresh(persp: Color, from: Square, to: Square) -> bool {
+    bucket(persp, from) != bucket(persp, to)
+}
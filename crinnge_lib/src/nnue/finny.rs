@@ -0,0 +1,187 @@
+use crinnge_bitboards::BitBoard;
+
+use crate::{board::Board, types::*};
+
+use super::{
+    feature::{Feature, InputLayout, LAYOUT},
+    simd, Aligned, Network, INPUT_BUCKETS,
+};
+
+#[derive(Copy, Clone, Debug)]
+struct FinnyEntry {
+    acc: Aligned,
+    pieces: [[BitBoard; 6]; 2],
+}
+
+/// Accumulator refresh cache, holding the last accumulator seen for each perspective, input bucket
+/// and mirroring along with the pieces it was built from.
+/// A refresh only applies the pieces that changed since the entry was last used
+#[derive(Clone, Debug)]
+pub struct FinnyTable<const BUCKETS: usize = INPUT_BUCKETS> {
+    entries: [[[FinnyEntry; 2]; BUCKETS]; 2],
+}
+
+impl<const BUCKETS: usize> FinnyTable<BUCKETS> {
+    /// An empty table for `net`, which must be rebuilt if the network changes
+    pub fn new(net: &Network) -> Self {
+        let entry = FinnyEntry {
+            acc: net.feature_bias,
+            pieces: [[BitBoard::empty(); 6]; 2],
+        };
+        Self {
+            entries: [[[entry; 2]; BUCKETS]; 2],
+        }
+    }
+
+    /// Refresh the `persp` half of an accumulator for `board` with any input layout and its
+    /// feature weights
+    fn refresh_with(
+        &mut self,
+        board: &Board,
+        persp: Color,
+        acc: &mut Aligned,
+        layout: &InputLayout,
+        weights: &[Aligned],
+    ) {
+        let king = board.king_square(persp);
+        let (bucket, mirrored) = layout.bucket(persp, king);
+        let entry = &mut self.entries[persp][bucket][mirrored as usize];
+
+        for color in [White, Black] {
+            for piece in [Pawn, Knight, Bishop, Rook, Queen, King] {
+                let pieces = board.pieces(piece)[color];
+                let cached = &mut entry.pieces[color][piece];
+
+                for square in pieces & !*cached {
+                    let feature = Feature {
+                        color,
+                        piece,
+                        square,
+                    };
+                    simd::add(&mut entry.acc, &weights[layout.index(feature, persp, king)]);
+                }
+                for square in *cached & !pieces {
+                    let feature = Feature {
+                        color,
+                        piece,
+                        square,
+                    };
+                    simd::sub(&mut entry.acc, &weights[layout.index(feature, persp, king)]);
+                }
+
+                *cached = pieces;
+            }
+        }
+
+        *acc = entry.acc;
+    }
+}
+
+impl FinnyTable {
+    /// Refresh the `persp` half of an accumulator for `board`
+    pub fn refresh(&mut self, net: &Network, board: &Board, persp: Color, acc: &mut Aligned) {
+        self.refresh_with(board, persp, acc, &LAYOUT, &net.feature_weights);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        board::Board,
+        nnue::{
+            feature::{Feature, InputLayout},
+            simd, Accumulator, INPUT_SIZE, NNUE,
+        },
+        rng::Rng,
+        types::*,
+    };
+
+    use super::FinnyTable;
+
+    #[test]
+    fn test_finny_refresh() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        ];
+
+        let mut finny = <FinnyTable>::new(&NNUE);
+        for fen in fens {
+            let board = Board::from_fen(fen).unwrap();
+            let mut expected = Accumulator::new(&NNUE);
+            board.refresh_accumulator(&NNUE, &mut expected);
+
+            let mut found = Accumulator::new(&NNUE);
+            finny.refresh(&NNUE, &board, White, &mut found.white);
+            finny.refresh(&NNUE, &board, Black, &mut found.black);
+            assert_eq!(expected, found, "{fen}");
+        }
+    }
+
+    #[test]
+    fn test_finny_refresh_across_buckets() {
+        // the first two ranks in one bucket and the rest in another, mirrored on the e-h files
+        let mut king_buckets = [1; 64];
+        king_buckets[..16].fill(0);
+        let layout = InputLayout {
+            king_buckets,
+            mirrored: true,
+        };
+
+        let mut rng = Rng::new(0x5eed);
+        let mut weights = vec![NNUE.feature_bias; INPUT_SIZE * 2];
+        for w in weights.iter_mut().flat_map(|w| w.iter_mut()) {
+            *w = rng.below(201) as i16 - 100;
+        }
+
+        let from_scratch = |board: &Board, persp: Color| {
+            let king = board.king_square(persp);
+            let mut acc = NNUE.feature_bias;
+            for color in [White, Black] {
+                for piece in [Pawn, Knight, Bishop, Rook, Queen, King] {
+                    for square in board.pieces(piece)[color] {
+                        let feature = Feature {
+                            color,
+                            piece,
+                            square,
+                        };
+                        simd::add(&mut acc, &weights[layout.index(feature, persp, king)]);
+                    }
+                }
+            }
+            acc
+        };
+
+        // walk both kings back and forth over the bucket and mirroring boundaries
+        let moves = [
+            "e1d2", "e8d7", "d2e3", "d7e6", "b2b4", "g7g5", "e3d2", "e6d7", "d2d3", "d7d6", "d3e2",
+            "d6e7", "e2e3", "e7e6", "e3d2", "e6d7",
+        ];
+        let mut board = Board::from_fen("4k3/pp4pp/8/8/8/8/PP4PP/4K3 w - - 0 1").unwrap();
+        let mut finny = FinnyTable::<2>::new(&NNUE);
+        let mut seen = Vec::new();
+        for mv in moves {
+            let mv = board
+                .legal_moves()
+                .into_iter()
+                .find(|m| m.coords() == mv)
+                .unwrap();
+            assert!(board.make_move_only(mv));
+
+            for persp in [White, Black] {
+                let mut found = NNUE.feature_bias;
+                finny.refresh_with(&board, persp, &mut found, &layout, &weights);
+                assert_eq!(from_scratch(&board, persp), found, "{}", board.fen());
+
+                let bucket = layout.bucket(persp, board.king_square(persp));
+                if !seen.contains(&bucket) {
+                    seen.push(bucket);
+                }
+            }
+        }
+        assert_eq!(seen.len(), 4, "every bucket and mirroring should be used");
+    }
+}
@@ -0,0 +1,495 @@
+//! Network file format
+//!
+//! A network file is a fixed size little-endian header followed by the little-endian network
+//! parameters: the i16 feature weights and bias, then either the i16 output weights and biases,
+//! or with dense layers the i8 weights and i32 biases of each layer, for each output bucket.
+//!
+//! | offset | size | field                             |
+//! |--------|------|-----------------------------------|
+//! | 0      | 4    | magic `CRNN`                      |
+//! | 4      | 4    | version                           |
+//! | 8      | 4    | input features per bucket         |
+//! | 12     | 4    | input buckets                     |
+//! | 16     | 4    | horizontal mirroring, 0 or 1      |
+//! | 20     | 64   | input bucket of each king square  |
+//! | 84     | 4    | hidden size                       |
+//! | 88     | 4    | feature transformer activation    |
+//! | 92     | 4    | output buckets                    |
+//! | 96     | 4    | L2 size, 0 without dense layers   |
+//! | 100    | 4    | L3 size                           |
+//! | 104    | 4    | dense layer activation            |
+//! | 108    | 4    | QA                                |
+//! | 112    | 4    | QB                                |
+//! | 116    | 4    | eval scale                        |
+//! | 120    | 8    | FNV-1a checksum of the parameters |
+
+use std::{fmt::Display, fs, mem::size_of, path::Path};
+
+use super::{
+    layers::{Activation, Dense, LayerStack},
+    Network, NetworkError, EVAL_SCALE, HIDDEN_SIZE, INPUT_BUCKETS, INPUT_SIZE, KING_BUCKETS,
+    L2_SIZE, L3_SIZE, MIRRORED, OUTPUT_BUCKETS, QA, QB,
+};
+
+pub const NETWORK_MAGIC: [u8; 4] = *b"CRNN";
+pub const NETWORK_VERSION: u32 = 5;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NetworkHeader {
+    pub version: u32,
+    pub input_size: u32,
+    pub input_buckets: u32,
+    pub mirrored: bool,
+    pub king_buckets: [u8; 64],
+    pub hidden_size: u32,
+    pub ft_activation: Activation,
+    pub output_buckets: u32,
+    pub l2_size: u32,
+    pub l3_size: u32,
+    pub layer_activation: Activation,
+    pub qa: i32,
+    pub qb: i32,
+    pub eval_scale: i32,
+    pub checksum: u64,
+}
+
+impl NetworkHeader {
+    pub const SIZE: usize = 128;
+
+    /// The header for `net` with the architecture this engine was compiled with
+    pub fn of(net: &Network, checksum: u64) -> Self {
+        Self {
+            version: NETWORK_VERSION,
+            input_size: INPUT_SIZE as u32,
+            input_buckets: INPUT_BUCKETS as u32,
+            mirrored: MIRRORED,
+            king_buckets: KING_BUCKETS,
+            hidden_size: HIDDEN_SIZE as u32,
+            ft_activation: net.ft_activation,
+            output_buckets: OUTPUT_BUCKETS as u32,
+            l2_size: L2_SIZE as u32,
+            l3_size: L3_SIZE as u32,
+            layer_activation: net.layer_activation,
+            qa: QA,
+            qb: QB,
+            eval_scale: EVAL_SCALE,
+            checksum,
+        }
+    }
+
+    pub fn architecture_matches(&self, other: &Self) -> bool {
+        self.input_size == other.input_size
+            && self.input_buckets == other.input_buckets
+            && self.mirrored == other.mirrored
+            && self.king_buckets == other.king_buckets
+            && self.hidden_size == other.hidden_size
+            && self.output_buckets == other.output_buckets
+            && self.l2_size == other.l2_size
+            && self.l3_size == other.l3_size
+            && self.qa == other.qa
+            && self.qb == other.qb
+            && self.eval_scale == other.eval_scale
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<Self, NetworkError> {
+        if bytes.len() < Self::SIZE {
+            return Err(NetworkError::WrongSize {
+                expected: Self::SIZE,
+                found: bytes.len(),
+            });
+        }
+        if bytes[0..4] != NETWORK_MAGIC {
+            return Err(NetworkError::BadMagic);
+        }
+
+        let mut reader = ByteReader { bytes, pos: 4 };
+        let version = reader.u32();
+        if version != NETWORK_VERSION {
+            return Err(NetworkError::UnsupportedVersion(version));
+        }
+
+        Ok(Self {
+            version,
+            input_size: reader.u32(),
+            input_buckets: reader.u32(),
+            mirrored: reader.u32() != 0,
+            king_buckets: reader.array(),
+            hidden_size: reader.u32(),
+            ft_activation: reader.activation()?,
+            output_buckets: reader.u32(),
+            l2_size: reader.u32(),
+            l3_size: reader.u32(),
+            layer_activation: reader.activation()?,
+            qa: reader.u32() as i32,
+            qb: reader.u32() as i32,
+            eval_scale: reader.u32() as i32,
+            checksum: u64::from_le_bytes(reader.array()),
+        })
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&NETWORK_MAGIC);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.input_size.to_le_bytes());
+        out.extend_from_slice(&self.input_buckets.to_le_bytes());
+        out.extend_from_slice(&u32::from(self.mirrored).to_le_bytes());
+        out.extend_from_slice(&self.king_buckets);
+        out.extend_from_slice(&self.hidden_size.to_le_bytes());
+        out.extend_from_slice(&(self.ft_activation as u32).to_le_bytes());
+        out.extend_from_slice(&self.output_buckets.to_le_bytes());
+        out.extend_from_slice(&self.l2_size.to_le_bytes());
+        out.extend_from_slice(&self.l3_size.to_le_bytes());
+        out.extend_from_slice(&(self.layer_activation as u32).to_le_bytes());
+        out.extend_from_slice(&self.qa.to_le_bytes());
+        out.extend_from_slice(&self.qb.to_le_bytes());
+        out.extend_from_slice(&self.eval_scale.to_le_bytes());
+        out.extend_from_slice(&self.checksum.to_le_bytes());
+    }
+}
+
+impl Display for NetworkHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}x{}{}->{} {}",
+            self.input_size,
+            self.input_buckets,
+            if self.mirrored { "hm" } else { "" },
+            self.hidden_size,
+            self.ft_activation,
+        )?;
+        if self.l2_size > 0 {
+            write!(
+                f,
+                "->{}->{} {}",
+                self.l2_size, self.l3_size, self.layer_activation
+            )?;
+        }
+        write!(
+            f,
+            "->1x{} QA {} QB {} scale {}",
+            self.output_buckets, self.qa, self.qb, self.eval_scale
+        )
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl ByteReader<'_> {
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let array = self.bytes[self.pos..self.pos + N].try_into().unwrap();
+        self.pos += N;
+        array
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.array())
+    }
+
+    fn activation(&mut self) -> Result<Activation, NetworkError> {
+        let activation = self.u32();
+        Activation::from_u32(activation).ok_or(NetworkError::UnknownActivation(activation))
+    }
+
+    fn read_into<T: Parameter>(&mut self, params: &mut [T]) {
+        for p in params {
+            *p = T::read(self);
+        }
+    }
+}
+
+/// A network parameter type stored little-endian
+trait Parameter: Copy {
+    const SIZE: usize;
+
+    fn read(reader: &mut ByteReader) -> Self;
+    fn write(self, out: &mut Vec<u8>);
+
+    fn write_all(params: &[Self], out: &mut Vec<u8>) {
+        for &p in params {
+            p.write(out);
+        }
+    }
+}
+
+macro_rules! impl_parameter {
+    ($($t:ty),*) => {$(
+        impl Parameter for $t {
+            const SIZE: usize = size_of::<$t>();
+
+            fn read(reader: &mut ByteReader) -> Self {
+                Self::from_le_bytes(reader.array())
+            }
+
+            fn write(self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+    )*};
+}
+
+impl_parameter!(i8, i16, i32);
+
+/// 64-bit FNV-1a hash
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Network {
+    /// Size in bytes of the parameters in a network file
+    pub const PARAMETERS_SIZE: usize = (INPUT_SIZE * INPUT_BUCKETS + 1) * HIDDEN_SIZE * i16::SIZE
+        + if Self::HAS_LAYERS {
+            LayerStack::<HIDDEN_SIZE, L2_SIZE, L3_SIZE>::SIZE * OUTPUT_BUCKETS
+        } else {
+            (HIDDEN_SIZE + 1) * OUTPUT_BUCKETS * i16::SIZE
+        };
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Box<Self>, NetworkError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), NetworkError> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Box<Self>, NetworkError> {
+        let header = NetworkHeader::read(bytes)?;
+
+        let mut net = Self::zeroed();
+        net.ft_activation = header.ft_activation;
+        net.layer_activation = header.layer_activation;
+
+        let expected_header = NetworkHeader::of(&net, header.checksum);
+        if !header.architecture_matches(&expected_header) {
+            return Err(NetworkError::ArchitectureMismatch {
+                expected: Box::new(expected_header),
+                found: Box::new(header),
+            });
+        }
+
+        let params = &bytes[NetworkHeader::SIZE..];
+        if params.len() != Self::PARAMETERS_SIZE {
+            return Err(NetworkError::WrongSize {
+                expected: NetworkHeader::SIZE + Self::PARAMETERS_SIZE,
+                found: bytes.len(),
+            });
+        }
+        let found = checksum(params);
+        if found != header.checksum {
+            return Err(NetworkError::ChecksumMismatch {
+                expected: header.checksum,
+                found,
+            });
+        }
+
+        let mut reader = ByteReader {
+            bytes: params,
+            pos: 0,
+        };
+        for weights in net.feature_weights.iter_mut() {
+            reader.read_into(&mut weights[..]);
+        }
+        reader.read_into(&mut net.feature_bias[..]);
+        if Self::HAS_LAYERS {
+            for stack in net.layers.iter_mut() {
+                read_dense(&mut reader, &mut stack.l1);
+                read_dense(&mut reader, &mut stack.l2);
+                read_dense(&mut reader, &mut stack.l3);
+            }
+        } else {
+            for weights in net.output_weights.iter_mut() {
+                reader.read_into(&mut weights[..]);
+            }
+            reader.read_into(&mut net.output_bias);
+        }
+        if !net.output_weights_in_range() {
+            return Err(NetworkError::OutputWeightsOutOfRange);
+        }
+
+        Ok(net)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut params = Vec::with_capacity(Self::PARAMETERS_SIZE);
+        for weights in self.feature_weights.iter() {
+            i16::write_all(&weights[..], &mut params);
+        }
+        i16::write_all(&self.feature_bias[..], &mut params);
+        if Self::HAS_LAYERS {
+            for stack in self.layers.iter() {
+                write_dense(&stack.l1, &mut params);
+                write_dense(&stack.l2, &mut params);
+                write_dense(&stack.l3, &mut params);
+            }
+        } else {
+            for weights in self.output_weights.iter() {
+                i16::write_all(&weights[..], &mut params);
+            }
+            i16::write_all(&self.output_bias, &mut params);
+        }
+
+        let mut bytes = Vec::with_capacity(NetworkHeader::SIZE + params.len());
+        NetworkHeader::of(self, checksum(&params)).write(&mut bytes);
+        bytes.extend_from_slice(&params);
+        bytes
+    }
+}
+
+fn read_dense<const IN: usize, const OUT: usize>(
+    reader: &mut ByteReader,
+    layer: &mut Dense<IN, OUT>,
+) {
+    for weights in layer.weights.iter_mut() {
+        reader.read_into(weights);
+    }
+    reader.read_into(&mut layer.bias);
+}
+
+fn write_dense<const IN: usize, const OUT: usize>(layer: &Dense<IN, OUT>, out: &mut Vec<u8>) {
+    for weights in layer.weights.iter() {
+        i8::write_all(weights, out);
+    }
+    i32::write_all(&layer.bias, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        board::Board,
+        nnue::{layers::Activation, Accumulator, EVAL_SCALE, HIDDEN_SIZE, NNUE, QA, QB},
+    };
+
+    use std::mem::size_of;
+
+    use super::{Network, NetworkError, NetworkHeader, NETWORK_MAGIC};
+
+    // header field offsets, following the order of `NetworkHeader::write`
+    const VERSION_OFFSET: usize = NETWORK_MAGIC.len();
+    const KING_BUCKETS_OFFSET: usize = VERSION_OFFSET + 4 * size_of::<u32>();
+    const HIDDEN_SIZE_OFFSET: usize = KING_BUCKETS_OFFSET + size_of::<[u8; 64]>();
+    const OUTPUT_BUCKETS_OFFSET: usize = HIDDEN_SIZE_OFFSET + 2 * size_of::<u32>();
+    const LAYER_ACTIVATION_OFFSET: usize = OUTPUT_BUCKETS_OFFSET + 3 * size_of::<u32>();
+
+    #[test]
+    fn test_network_file_round_trip() {
+        let bytes = NNUE.to_bytes();
+        assert_eq!(bytes.len(), NetworkHeader::SIZE + Network::PARAMETERS_SIZE);
+
+        let net = Network::from_bytes(&bytes).unwrap();
+        assert!(net.feature_weights == NNUE.feature_weights);
+        assert!(net.feature_bias == NNUE.feature_bias);
+        assert!(net.output_weights == NNUE.output_weights);
+        assert_eq!(net.output_bias, NNUE.output_bias);
+
+        let acc = Accumulator::new(&NNUE);
+        assert_eq!(net.evaluate(&acc.white, 0), NNUE.evaluate(&acc.white, 0));
+        assert_eq!(net.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_network_file_errors() {
+        let bytes = NNUE.to_bytes();
+
+        assert!(matches!(
+            Network::from_bytes(include_bytes!("crinnge_v1-10.bin")),
+            Err(NetworkError::BadMagic)
+        ));
+        assert!(matches!(
+            Network::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(NetworkError::WrongSize { .. })
+        ));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[VERSION_OFFSET] += 1;
+        assert!(matches!(
+            Network::from_bytes(&wrong_version),
+            Err(NetworkError::UnsupportedVersion(_))
+        ));
+
+        let mut wrong_hidden = bytes.clone();
+        wrong_hidden[HIDDEN_SIZE_OFFSET] += 1;
+        assert!(matches!(
+            Network::from_bytes(&wrong_hidden),
+            Err(NetworkError::ArchitectureMismatch { .. })
+        ));
+
+        let mut wrong_buckets = bytes.clone();
+        wrong_buckets[KING_BUCKETS_OFFSET + 63] += 1;
+        assert!(matches!(
+            Network::from_bytes(&wrong_buckets),
+            Err(NetworkError::ArchitectureMismatch { .. })
+        ));
+
+        let mut wrong_activation = bytes.clone();
+        wrong_activation[LAYER_ACTIVATION_OFFSET] = 3;
+        assert!(matches!(
+            Network::from_bytes(&wrong_activation),
+            Err(NetworkError::UnknownActivation(3))
+        ));
+
+        let mut wrong_output_buckets = bytes.clone();
+        wrong_output_buckets[OUTPUT_BUCKETS_OFFSET] += 1;
+        assert!(matches!(
+            Network::from_bytes(&wrong_output_buckets),
+            Err(NetworkError::ArchitectureMismatch { .. })
+        ));
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            Network::from_bytes(&corrupted),
+            Err(NetworkError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ft_activation_from_header() {
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let mut acc = Accumulator::new(&NNUE);
+        board.refresh_accumulator(&NNUE, &mut acc);
+        let crelu = |i: usize| i32::from(acc.white[i]).clamp(0, QA);
+
+        let mut net = Network::from_bytes(&NNUE.to_bytes()).unwrap();
+        for w in net.output_weights.iter_mut().flat_map(|w| w.iter_mut()) {
+            *w = (*w).clamp(i8::MIN.into(), i8::MAX.into());
+        }
+        let w = |i: usize| i32::from(net.output_weights[0][i]);
+
+        for activation in [Activation::CReLU, Activation::SCReLU, Activation::Pairwise] {
+            let sum = match activation {
+                Activation::CReLU => (0..HIDDEN_SIZE).map(|i| crelu(i) * w(i)).sum::<i32>(),
+                Activation::SCReLU => {
+                    (0..HIDDEN_SIZE)
+                        .map(|i| crelu(i) * crelu(i) * w(i))
+                        .sum::<i32>()
+                        / QA
+                }
+                Activation::Pairwise => {
+                    (0..HIDDEN_SIZE / 2)
+                        .map(|i| crelu(i) * crelu(i + HIDDEN_SIZE / 2) * w(i))
+                        .sum::<i32>()
+                        / QA
+                }
+            };
+            let expected = (sum + i32::from(net.output_bias[0])) * EVAL_SCALE / (QA * QB);
+
+            net.ft_activation = activation;
+            let loaded = Network::from_bytes(&net.to_bytes()).unwrap();
+            assert_eq!(loaded.ft_activation, activation);
+            assert_eq!(loaded.evaluate(&acc.white, 0), expected, "{activation}");
+        }
+
+        net.output_weights[0][0] = 1000;
+        assert!(matches!(
+            Network::from_bytes(&net.to_bytes()),
+            Err(NetworkError::OutputWeightsOutOfRange)
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+//! Quantised dense layers after the feature transformer
+//!
+//! Layer inputs are u8 activations where `QA` is 1.0, weights are i8 where `QW` is 1.0,
+//! and biases and outputs are i32 where `QA * QW` is 1.0
+
+use super::QA;
+
+/// Quantisation of the dense layer weights
+pub const QW: i32 = 64;
+
+// activations must fit in a u8
+const _: () = assert!(QA <= u8::MAX as i32);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Activation {
+    /// Clipped ReLU
+    #[default]
+    CReLU = 0,
+    /// Squared clipped ReLU
+    SCReLU = 1,
+    /// Clipped ReLU of both halves of the layer multiplied together, halving its size
+    Pairwise = 2,
+}
+
+impl Activation {
+    pub fn from_u32(n: u32) -> Option<Self> {
+        match n {
+            0 => Some(Self::CReLU),
+            1 => Some(Self::SCReLU),
+            2 => Some(Self::Pairwise),
+            _ => None,
+        }
+    }
+
+    /// Activate i32 layer outputs into u8 inputs for the next layer.
+    /// Pairwise only fills the first half of `output` and zeroes the rest
+    pub fn apply(self, input: &[i32], output: &mut [u8]) {
+        const ONE: i32 = QA * QW;
+
+        match self {
+            Self::CReLU => {
+                for (o, &i) in output.iter_mut().zip(input) {
+                    *o = (i.clamp(0, ONE) / QW) as u8;
+                }
+            }
+            Self::SCReLU => {
+                for (o, &i) in output.iter_mut().zip(input) {
+                    let i = i.clamp(0, ONE);
+                    *o = (i * i / (ONE * QW)) as u8;
+                }
+            }
+            Self::Pairwise => {
+                let (left, right) = input.split_at(input.len() / 2);
+                let (paired, rest) = output.split_at_mut(input.len() / 2);
+                for (o, (&l, &r)) in paired.iter_mut().zip(left.iter().zip(right)) {
+                    *o = ((l.clamp(0, ONE) / QW) * (r.clamp(0, ONE) / QW) / QA) as u8;
+                }
+                rest.fill(0);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Activation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CReLU => write!(f, "CReLU"),
+            Self::SCReLU => write!(f, "SCReLU"),
+            Self::Pairwise => write!(f, "pairwise"),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dense<const IN: usize, const OUT: usize> {
+    pub weights: [[i8; IN]; OUT],
+    pub bias: [i32; OUT],
+}
+
+impl<const IN: usize, const OUT: usize> Dense<IN, OUT> {
+    pub const SIZE: usize = IN * OUT + OUT * 4;
+
+    pub fn forward(&self, input: &[u8; IN], output: &mut [i32; OUT]) {
+        for ((o, weights), &bias) in output.iter_mut().zip(&self.weights).zip(&self.bias) {
+            *o = bias
+                + input
+                    .iter()
+                    .zip(weights)
+                    .map(|(&i, &w)| i32::from(i) * i32::from(w))
+                    .sum::<i32>();
+        }
+    }
+}
+
+/// The dense layers for one output bucket, `L1` is the size of the feature transformer output
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LayerStack<const L1: usize, const L2: usize, const L3: usize> {
+    pub l1: Dense<L1, L2>,
+    pub l2: Dense<L2, L3>,
+    pub l3: Dense<L3, 1>,
+}
+
+impl<const L1: usize, const L2: usize, const L3: usize> LayerStack<L1, L2, L3> {
+    pub const SIZE: usize = Dense::<L1, L2>::SIZE + Dense::<L2, L3>::SIZE + Dense::<L3, 1>::SIZE;
+
+    /// Output of the final layer, where `QA * QW` is 1.0
+    pub fn evaluate(&self, input: &[u8; L1], activation: Activation) -> i32 {
+        let mut l1_out = [0; L2];
+        let mut l2_in = [0; L2];
+        self.l1.forward(input, &mut l1_out);
+        activation.apply(&l1_out, &mut l2_in);
+
+        let mut l2_out = [0; L3];
+        let mut l3_in = [0; L3];
+        self.l2.forward(&l2_in, &mut l2_out);
+        activation.apply(&l2_out, &mut l3_in);
+
+        let mut output = [0];
+        self.l3.forward(&l3_in, &mut output);
+        output[0]
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use crate::rng::Rng;
+
+    use super::{Activation, Dense, LayerStack, QA, QW};
+
+    /// Float version of an activation, where 1.0 is the clipping point
+    pub(crate) fn activate_float(input: &[f32], activation: Activation) -> Vec<f32> {
+        let crelu = |x: f32| x.clamp(0.0, 1.0);
+        match activation {
+            Activation::CReLU => input.iter().map(|&x| crelu(x)).collect(),
+            Activation::SCReLU => input.iter().map(|&x| crelu(x) * crelu(x)).collect(),
+            Activation::Pairwise => {
+                let (left, right) = input.split_at(input.len() / 2);
+                let mut out: Vec<f32> = left
+                    .iter()
+                    .zip(right)
+                    .map(|(&l, &r)| crelu(l) * crelu(r))
+                    .collect();
+                out.resize(input.len(), 0.0);
+                out
+            }
+        }
+    }
+
+    /// Float version of a quantised layer stack, taking activations in [0, 1]
+    pub(crate) fn evaluate_float<const L1: usize, const L2: usize, const L3: usize>(
+        stack: &LayerStack<L1, L2, L3>,
+        input: &[f32; L1],
+        activation: Activation,
+    ) -> f32 {
+        fn forward<const IN: usize, const OUT: usize>(
+            layer: &Dense<IN, OUT>,
+            input: &[f32],
+        ) -> Vec<f32> {
+            layer
+                .weights
+                .iter()
+                .zip(layer.bias)
+                .map(|(weights, bias)| {
+                    let sum: f32 = input
+                        .iter()
+                        .zip(weights)
+                        .map(|(&i, &w)| i * f32::from(w) / QW as f32)
+                        .sum();
+                    sum + bias as f32 / (QA * QW) as f32
+                })
+                .collect()
+        }
+
+        let l2_in = activate_float(&forward(&stack.l1, input), activation);
+        let l3_in = activate_float(&forward(&stack.l2, &l2_in), activation);
+        forward(&stack.l3, &l3_in)[0]
+    }
+
+    #[test]
+    fn test_layer_stack_matches_float() {
+        let mut rng = Rng::new(1);
+
+        for activation in [Activation::CReLU, Activation::SCReLU, Activation::Pairwise] {
+            for _ in 0..50 {
+                let mut stack = LayerStack::<64, 16, 32> {
+                    l1: Dense {
+                        weights: [[0; 64]; 16],
+                        bias: [0; 16],
+                    },
+                    l2: Dense {
+                        weights: [[0; 16]; 32],
+                        bias: [0; 32],
+                    },
+                    l3: Dense {
+                        weights: [[0; 32]; 1],
+                        bias: [0; 1],
+                    },
+                };
+                for w in stack.l1.weights.iter_mut().flatten() {
+                    *w = rng.within(20) as i8;
+                }
+                for b in stack.l1.bias.iter_mut() {
+                    *b = rng.within(QA * QW);
+                }
+                for w in stack.l2.weights.iter_mut().flatten() {
+                    *w = rng.within(40) as i8;
+                }
+                for b in stack.l2.bias.iter_mut() {
+                    *b = rng.within(QA * QW);
+                }
+                for w in stack.l3.weights[0].iter_mut() {
+                    *w = rng.within(127) as i8;
+                }
+                stack.l3.bias[0] = rng.within(QA * QW);
+
+                let mut input = [0u8; 64];
+                for i in input.iter_mut() {
+                    *i = rng.below(QA as usize + 1) as u8;
+                }
+                let float_input = input.map(|i| f32::from(i) / QA as f32);
+
+                let quantised = stack.evaluate(&input, activation) as f32 / (QA * QW) as f32;
+                let float = evaluate_float(&stack, &float_input, activation);
+                assert!(
+                    (quantised - float).abs() < 0.05,
+                    "{activation}: quantised {quantised} float {float}"
+                );
+            }
+        }
+    }
+}
@@ -1,14 +1,133 @@
-const HIDDEN_SIZE: usize = 64;
-const EVAL_SCALE: i32 = 400;
-const QA: i32 = 255;
-const QB: i32 = 64;
+pub const INPUT_SIZE: usize = 768;
+/// Input bucket for each square of a side's king, from that side's point of view.
+/// With mirroring only the a-d files are used
+#[rustfmt::skip]
+const KING_BUCKETS: [u8; 64] = [
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+];
+pub const INPUT_BUCKETS: usize = {
+    let mut max = 0;
+    let mut i = 0;
+    while i < 64 {
+        if KING_BUCKETS[i] > max {
+            max = KING_BUCKETS[i];
+        }
+        i += 1;
+    }
+    max as usize + 1
+};
+/// Mirror features horizontally when a side's king is on the e-h files
+const MIRRORED: bool = false;
+pub const HIDDEN_SIZE: usize = 64;
+/// Sizes of the dense layers after the feature transformer, 0 to output directly from it
+const L2_SIZE: usize = 0;
+const L3_SIZE: usize = 0;
+/// Output buckets, selected by the number of pieces on the board
+pub const OUTPUT_BUCKETS: usize = 1;
+pub const EVAL_SCALE: i32 = 400;
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
 
 pub mod feature;
 pub mod accumulator;
 pub mod network;
+pub mod format;
+pub mod simd;
+pub mod finny;
+pub mod layers;
+
+use std::{
+    fmt::Debug,
+    mem::{align_of, offset_of, size_of, MaybeUninit},
+    ops::Deref,
+    ptr,
+    sync::{Arc, Mutex},
+};
 
 pub use accumulator::*;
 pub use network::*;
+pub use format::*;
+pub use finny::*;
+
+/// The default network embedded in the binary, a raw dump of the feature transformer and output
+/// parameters with no dense layers
+pub static NNUE: Network = {
+    const RAW: &[u8] = include_bytes!("crinnge_v1-10.bin");
+    const PARAMS_END: usize = offset_of!(Network, output_bias) + size_of::<[i16; OUTPUT_BUCKETS]>();
+    // the dump is padded to the alignment of the weights, any other size is a different architecture
+    assert!(!<Network>::HAS_LAYERS);
+    assert!(RAW.len() == PARAMS_END.next_multiple_of(align_of::<Aligned>()));
+
+    let mut net = MaybeUninit::<Network>::zeroed();
+    // SAFETY: the parameters are plain integers and the copy stays inside both allocations,
+    // the remaining fields are valid as zeroes
+    unsafe {
+        ptr::copy_nonoverlapping(RAW.as_ptr(), net.as_mut_ptr().cast::<u8>(), PARAMS_END);
+        net.assume_init()
+    }
+};
+
+/// Name reported for the embedded default network
+pub const DEFAULT_NETWORK_NAME: &str = "crinnge_v1-10 (embedded)";
+
+/// A network kept alive for as long as something uses it, either the embedded default or a
+/// loaded network
+#[derive(Clone)]
+pub enum NetworkRef {
+    Default,
+    Loaded(Arc<Network>),
+}
+
+impl Deref for NetworkRef {
+    type Target = Network;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Default => &NNUE,
+            Self::Loaded(net) => net,
+        }
+    }
+}
+
+impl Debug for NetworkRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", NetworkHeader::of(self, 0))
+    }
+}
+
+static ACTIVE_NETWORK: Mutex<NetworkRef> = Mutex::new(NetworkRef::Default);
+/// Name of the active network, empty for the default network
+static ACTIVE_NETWORK_NAME: Mutex<String> = Mutex::new(String::new());
+
+/// The network that new thread data uses for accumulator updates and evaluation
+pub fn network() -> NetworkRef {
+    ACTIVE_NETWORK.lock().unwrap().clone()
+}
+
+/// Switch to a new network. Existing thread data keeps the network it was created with until it
+/// is moved to the new one, and a replaced network is freed once nothing uses it
+pub fn set_network(net: Box<Network>, name: &str) {
+    *ACTIVE_NETWORK.lock().unwrap() = NetworkRef::Loaded(Arc::from(net));
+    *ACTIVE_NETWORK_NAME.lock().unwrap() = name.to_string();
+}
 
-pub static NNUE: Network = unsafe { std::mem::transmute(*include_bytes!("crinnge_v1-10.bin")) };
+/// Switch back to the embedded default network
+pub fn use_default_network() {
+    *ACTIVE_NETWORK.lock().unwrap() = NetworkRef::Default;
+    ACTIVE_NETWORK_NAME.lock().unwrap().clear();
+}
 
+/// A description of the active network for `info string` output
+pub fn network_description() -> String {
+    let name = ACTIVE_NETWORK_NAME.lock().unwrap();
+    let name = if name.is_empty() { DEFAULT_NETWORK_NAME } else { name.as_str() };
+    format!("using network {name} ({})", NetworkHeader::of(&network(), 0))
+}
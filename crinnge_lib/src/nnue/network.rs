@@ -1,28 +1,140 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    fmt::Display,
+    ops::{Deref, DerefMut},
+};
 
-use super::{EVAL_SCALE, HIDDEN_SIZE, QA, QB};
+use super::{
+    layers::{Activation, LayerStack, QW},
+    simd, NetworkHeader, EVAL_SCALE, HIDDEN_SIZE, INPUT_BUCKETS, INPUT_SIZE, L2_SIZE, L3_SIZE,
+    OUTPUT_BUCKETS, QA, QB,
+};
 
+// SCReLU and pairwise outputs sum up to `HIDDEN_SIZE` products of two activations and an i8
+// weight, which must fit in an i32
+const _: () = assert!(HIDDEN_SIZE as i64 * (QA * QA) as i64 * i8::MAX as i64 <= i32::MAX as i64);
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    UnknownActivation(u32),
+    OutputWeightsOutOfRange,
+    ArchitectureMismatch {
+        expected: Box<NetworkHeader>,
+        found: Box<NetworkHeader>,
+    },
+    WrongSize {
+        expected: usize,
+        found: usize,
+    },
+    ChecksumMismatch {
+        expected: u64,
+        found: u64,
+    },
+}
+
+impl Display for NetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Failed to read network file: {e}"),
+            Self::BadMagic => write!(f, "Not a CriNNge network file"),
+            Self::UnsupportedVersion(v) => write!(f, "Unsupported network file version {v}"),
+            Self::UnknownActivation(a) => write!(f, "Unknown network activation {a}"),
+            Self::OutputWeightsOutOfRange => {
+                write!(f, "Network output weights are too large for its activation")
+            }
+            Self::ArchitectureMismatch { expected, found } => {
+                write!(f, "Network architecture is {found}, expected {expected}")
+            }
+            Self::WrongSize { expected, found } => write!(
+                f,
+                "Network file is {found} bytes, expected {expected} bytes"
+            ),
+            Self::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Network checksum is {found:016x}, expected {expected:016x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+impl From<std::io::Error> for NetworkError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// The network with its dense layer sizes, which default to the engine's configuration
 #[repr(C)]
-pub struct Network {
-    pub feature_weights: [Aligned; 768],
+pub struct Network<const L2: usize = L2_SIZE, const L3: usize = L3_SIZE> {
+    pub feature_weights: [Aligned; INPUT_SIZE * INPUT_BUCKETS],
     pub feature_bias: Aligned,
-    pub output_weights: Aligned,
-    pub output_bias: i16,
+    /// Direct output from the feature transformer, only used without dense layers
+    pub output_weights: [Aligned; OUTPUT_BUCKETS],
+    pub output_bias: [i16; OUTPUT_BUCKETS],
+    pub layers: [LayerStack<HIDDEN_SIZE, L2, L3>; OUTPUT_BUCKETS],
+    pub ft_activation: Activation,
+    pub layer_activation: Activation,
 }
 
-impl Network {
-    pub fn evaluate(&self, acc: &Aligned) -> i32 {
-        let mut output = self.output_bias as i32;
-        for (&n, &ow) in acc.iter().zip(self.output_weights.iter()) {
-            output += crelu(n) * i32::from(ow)
+impl<const L2: usize, const L3: usize> Network<L2, L3> {
+    /// Whether the feature transformer feeds into dense layers rather than straight to the output
+    pub const HAS_LAYERS: bool = L2 != 0;
+
+    /// A network with every parameter zeroed, allocated straight on the heap
+    pub fn zeroed() -> Box<Self> {
+        // SAFETY: all-zero is a valid Network
+        unsafe { Box::new_zeroed().assume_init() }
+    }
+
+    pub fn evaluate(&self, acc: &Aligned, bucket: usize) -> i32 {
+        if Self::HAS_LAYERS {
+            // scale the accumulator to the dense layer output quantisation to share activations
+            let scaled = acc.map(|v| i32::from(v) * QW);
+            let mut input = [0; HIDDEN_SIZE];
+            self.ft_activation.apply(&scaled, &mut input);
+            let output = self.layers[bucket].evaluate(&input, self.layer_activation);
+
+            return output * EVAL_SCALE / (QA * QW);
         }
 
+        let weights = &self.output_weights[bucket];
+        let output = match self.ft_activation {
+            Activation::CReLU => simd::crelu_dot(acc, weights),
+            // squared activations are in QA * QA, bring them back to QA
+            Activation::SCReLU => simd::screlu_dot(acc, weights) / QA,
+            Activation::Pairwise => simd::pairwise_dot(acc, weights) / QA,
+        } + self.output_bias[bucket] as i32;
+
         output * EVAL_SCALE / (QA * QB)
     }
+
+    /// Whether the output weights fit the activation, SCReLU and pairwise need them in the i8 range
+    pub fn output_weights_in_range(&self) -> bool {
+        Self::HAS_LAYERS
+            || self.ft_activation == Activation::CReLU
+            || self
+                .output_weights
+                .iter()
+                .flat_map(|w| w.iter())
+                .all(|&w| i8::try_from(w).is_ok())
+    }
+}
+
+/// Output bucket for a position with `piece_count` pieces on the board, kings included
+#[inline(always)]
+pub fn output_bucket(piece_count: u32) -> usize {
+    output_bucket_of::<OUTPUT_BUCKETS>(piece_count)
 }
 
-fn crelu(n: i16) -> i32 {
-    i32::from(n).clamp(0, QA)
+/// Output bucket among `N` buckets, each covering an equal range of piece counts
+#[inline(always)]
+fn output_bucket_of<const N: usize>(piece_count: u32) -> usize {
+    let divisor = const { 32_usize.div_ceil(N) };
+    (piece_count as usize).saturating_sub(2) / divisor
 }
 
 #[repr(align(64))]
@@ -42,3 +154,77 @@ impl DerefMut for Aligned {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{output_bucket_of, Activation, Aligned, Network, EVAL_SCALE, HIDDEN_SIZE, QA, QW};
+    use crate::{
+        nnue::layers::tests::{activate_float, evaluate_float},
+        rng::Rng,
+    };
+
+    #[test]
+    fn test_evaluate_with_layers_matches_float() {
+        let mut rng = Rng::new(2);
+
+        let activations = [Activation::CReLU, Activation::SCReLU, Activation::Pairwise];
+        for ft_activation in activations {
+            for layer_activation in activations {
+                let mut net = Network::<16, 32>::zeroed();
+                net.ft_activation = ft_activation;
+                net.layer_activation = layer_activation;
+
+                let stack = &mut net.layers[0];
+                for w in stack.l1.weights.iter_mut().flatten() {
+                    *w = rng.within(20) as i8;
+                }
+                for b in stack.l1.bias.iter_mut() {
+                    *b = rng.within(QA * QW);
+                }
+                for w in stack.l2.weights.iter_mut().flatten() {
+                    *w = rng.within(40) as i8;
+                }
+                for b in stack.l2.bias.iter_mut() {
+                    *b = rng.within(QA * QW);
+                }
+                for w in stack.l3.weights[0].iter_mut() {
+                    *w = rng.within(127) as i8;
+                }
+                stack.l3.bias[0] = rng.within(QA * QW);
+
+                // reach past both ends of the clipping range
+                let mut acc = Aligned([0; HIDDEN_SIZE]);
+                for v in acc.iter_mut() {
+                    *v = (rng.within(QA) + QA / 2) as i16;
+                }
+                let float_acc: Vec<f32> = acc.iter().map(|&v| f32::from(v) / QA as f32).collect();
+                let float_input = activate_float(&float_acc, ft_activation);
+
+                let quantised = net.evaluate(&acc, 0) as f32 / EVAL_SCALE as f32;
+                let float = evaluate_float(
+                    &net.layers[0],
+                    &float_input.try_into().unwrap(),
+                    layer_activation,
+                );
+                assert!(
+                    (quantised - float).abs() < 0.05,
+                    "{ft_activation}/{layer_activation}: quantised {quantised} float {float}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_bucket() {
+        // 8 buckets of 4 piece counts each, starting from the two kings
+        assert_eq!(output_bucket_of::<8>(2), 0);
+        assert_eq!(output_bucket_of::<8>(32), 7);
+        for bucket in 1..8 {
+            let first = 2 + 4 * bucket as u32;
+            assert_eq!(output_bucket_of::<8>(first - 1), bucket - 1);
+            assert_eq!(output_bucket_of::<8>(first), bucket);
+        }
+
+        assert!((2..=32).all(|count| output_bucket_of::<1>(count) == 0));
+    }
+}
@@ -0,0 +1,474 @@
+//! Accumulator update and evaluation kernels
+//!
+//! Each instruction set gets its own kernels, the best one supported by the CPU is picked once
+//! at runtime so a single binary runs at full speed everywhere. All kernels use wrapping i16
+//! arithmetic and exact i32 sums so they produce bit-identical results to the scalar fallback.
+//! The SCReLU and pairwise kernels multiply activations by weights in i16, so they need output
+//! weights in the i8 range.
+
+use std::sync::LazyLock;
+
+use super::{Aligned, HIDDEN_SIZE, QA};
+
+// every vector width must divide both halves of the hidden layer
+const _: () = assert!(HIDDEN_SIZE.is_multiple_of(64));
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SimdLevel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Sse41,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    #[cfg(target_arch = "x86_64")]
+    Avx512,
+}
+
+impl SimdLevel {
+    /// The best instruction set supported by this CPU
+    pub fn detect() -> Self {
+        Self::available().pop().unwrap()
+    }
+
+    /// All instruction sets supported by this CPU, from worst to best
+    pub fn available() -> Vec<Self> {
+        #[allow(unused_mut)]
+        let mut levels = vec![Self::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sse4.1") {
+                levels.push(Self::Sse41);
+            }
+            if is_x86_feature_detected!("avx2") {
+                levels.push(Self::Avx2);
+            }
+            if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") {
+                levels.push(Self::Avx512);
+            }
+        }
+        levels
+    }
+}
+
+static SIMD_LEVEL: LazyLock<SimdLevel> = LazyLock::new(SimdLevel::detect);
+
+/// The instruction set used by the NNUE kernels
+pub fn simd_level() -> SimdLevel {
+    *SIMD_LEVEL
+}
+
+// dispatch to the kernel for `level`, which must be one of `SimdLevel::available()`
+macro_rules! dispatch {
+    ($level:expr, $kernel:ident($($arg:expr),*)) => {
+        match $level {
+            SimdLevel::Scalar => $crate::nnue::simd::scalar::$kernel($($arg),*),
+            // SAFETY: the level was checked to be supported by the CPU
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse41 => unsafe { $crate::nnue::simd::sse41::$kernel($($arg),*) },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx2 => unsafe { $crate::nnue::simd::avx2::$kernel($($arg),*) },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx512 => unsafe { $crate::nnue::simd::avx512::$kernel($($arg),*) },
+        }
+    };
+}
+
+/// `acc += w`
+#[inline]
+pub fn add(acc: &mut Aligned, w: &Aligned) {
+    dispatch!(simd_level(), add(acc, w))
+}
+
+/// `acc -= w`
+#[inline]
+pub fn sub(acc: &mut Aligned, w: &Aligned) {
+    dispatch!(simd_level(), sub(acc, w))
+}
+
+/// `dst = src + add - sub`
+#[inline]
+pub fn add_sub(src: &Aligned, dst: &mut Aligned, add: &Aligned, sub: &Aligned) {
+    dispatch!(simd_level(), add_sub(src, dst, add, sub))
+}
+
+/// `dst = src + add - sub1 - sub2`
+#[inline]
+pub fn add_sub2(src: &Aligned, dst: &mut Aligned, add: &Aligned, sub1: &Aligned, sub2: &Aligned) {
+    dispatch!(simd_level(), add_sub2(src, dst, add, sub1, sub2))
+}
+
+/// `dst = src + add1 + add2 - sub1 - sub2`
+#[inline]
+pub fn add2_sub2(src: &Aligned, dst: &mut Aligned, adds: [&Aligned; 2], subs: [&Aligned; 2]) {
+    dispatch!(simd_level(), add2_sub2(src, dst, adds, subs))
+}
+
+/// Sum of `crelu(acc) * w`
+#[inline]
+pub fn crelu_dot(acc: &Aligned, w: &Aligned) -> i32 {
+    dispatch!(simd_level(), crelu_dot(acc, w))
+}
+
+/// Sum of `crelu(acc)^2 * w`, weights must be in the i8 range
+#[inline]
+pub fn screlu_dot(acc: &Aligned, w: &Aligned) -> i32 {
+    dispatch!(simd_level(), screlu_dot(acc, w))
+}
+
+/// Sum of `crelu(left half of acc) * crelu(right half of acc) * w` over the first half of `w`,
+/// weights must be in the i8 range
+#[inline]
+pub fn pairwise_dot(acc: &Aligned, w: &Aligned) -> i32 {
+    dispatch!(simd_level(), pairwise_dot(acc, w))
+}
+
+mod scalar {
+    use super::{Aligned, HIDDEN_SIZE, QA};
+
+    pub fn add(acc: &mut Aligned, w: &Aligned) {
+        for (a, &w) in acc.iter_mut().zip(w.iter()) {
+            *a = a.wrapping_add(w);
+        }
+    }
+
+    pub fn sub(acc: &mut Aligned, w: &Aligned) {
+        for (a, &w) in acc.iter_mut().zip(w.iter()) {
+            *a = a.wrapping_sub(w);
+        }
+    }
+
+    pub fn add_sub(src: &Aligned, dst: &mut Aligned, add: &Aligned, sub: &Aligned) {
+        for (i, d) in dst.iter_mut().enumerate() {
+            *d = src[i].wrapping_add(add[i]).wrapping_sub(sub[i]);
+        }
+    }
+
+    pub fn add_sub2(
+        src: &Aligned,
+        dst: &mut Aligned,
+        add: &Aligned,
+        sub1: &Aligned,
+        sub2: &Aligned,
+    ) {
+        for (i, d) in dst.iter_mut().enumerate() {
+            *d = src[i]
+                .wrapping_add(add[i])
+                .wrapping_sub(sub1[i])
+                .wrapping_sub(sub2[i]);
+        }
+    }
+
+    pub fn add2_sub2(src: &Aligned, dst: &mut Aligned, adds: [&Aligned; 2], subs: [&Aligned; 2]) {
+        for (i, d) in dst.iter_mut().enumerate() {
+            *d = src[i]
+                .wrapping_add(adds[0][i])
+                .wrapping_add(adds[1][i])
+                .wrapping_sub(subs[0][i])
+                .wrapping_sub(subs[1][i]);
+        }
+    }
+
+    pub fn crelu_dot(acc: &Aligned, w: &Aligned) -> i32 {
+        acc.iter()
+            .zip(w.iter())
+            .map(|(&a, &w)| i32::from(a).clamp(0, QA) * i32::from(w))
+            .sum()
+    }
+
+    pub fn screlu_dot(acc: &Aligned, w: &Aligned) -> i32 {
+        acc.iter()
+            .zip(w.iter())
+            .map(|(&a, &w)| {
+                let a = i32::from(a).clamp(0, QA);
+                a * a * i32::from(w)
+            })
+            .sum()
+    }
+
+    pub fn pairwise_dot(acc: &Aligned, w: &Aligned) -> i32 {
+        let (left, right) = acc.split_at(HIDDEN_SIZE / 2);
+        left.iter()
+            .zip(right)
+            .zip(w.iter())
+            .map(|((&l, &r), &w)| {
+                i32::from(l).clamp(0, QA) * i32::from(r).clamp(0, QA) * i32::from(w)
+            })
+            .sum()
+    }
+}
+
+// pointers to the `i`th element of an accumulator or weight row, for the load and store intrinsics
+#[cfg(target_arch = "x86_64")]
+macro_rules! ptr {
+    ($a:expr, $i:expr) => {
+        $a.as_ptr().add($i).cast()
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+macro_rules! ptr_mut {
+    ($a:expr, $i:expr) => {
+        $a.as_mut_ptr().add($i).cast()
+    };
+}
+
+// generates the kernels for one instruction set from its vector type and intrinsics
+#[cfg(target_arch = "x86_64")]
+macro_rules! simd_kernels {
+    (
+        feature: $feature:literal,
+        vector: $vec:ty,
+        zero: $zero:ident,
+        splat: $splat:ident,
+        load: $load:ident,
+        store: $store:ident,
+        add: $add:ident,
+        sub: $sub:ident,
+        max: $max:ident,
+        min: $min:ident,
+        mullo: $mullo:ident,
+        madd: $madd:ident,
+        add32: $add32:ident $(,)?
+    ) => {
+        use std::{arch::x86_64::*, mem::size_of};
+
+        use super::{Aligned, HIDDEN_SIZE, QA};
+
+        const LANES: usize = size_of::<$vec>() / size_of::<i16>();
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn add(acc: &mut Aligned, w: &Aligned) {
+            for i in (0..HIDDEN_SIZE).step_by(LANES) {
+                $store(
+                    ptr_mut!(acc, i),
+                    $add($load(ptr!(acc, i)), $load(ptr!(w, i))),
+                );
+            }
+        }
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn sub(acc: &mut Aligned, w: &Aligned) {
+            for i in (0..HIDDEN_SIZE).step_by(LANES) {
+                $store(
+                    ptr_mut!(acc, i),
+                    $sub($load(ptr!(acc, i)), $load(ptr!(w, i))),
+                );
+            }
+        }
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn add_sub(src: &Aligned, dst: &mut Aligned, add: &Aligned, sub: &Aligned) {
+            for i in (0..HIDDEN_SIZE).step_by(LANES) {
+                let v = $sub(
+                    $add($load(ptr!(src, i)), $load(ptr!(add, i))),
+                    $load(ptr!(sub, i)),
+                );
+                $store(ptr_mut!(dst, i), v);
+            }
+        }
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn add_sub2(
+            src: &Aligned,
+            dst: &mut Aligned,
+            add: &Aligned,
+            sub1: &Aligned,
+            sub2: &Aligned,
+        ) {
+            for i in (0..HIDDEN_SIZE).step_by(LANES) {
+                let v = $add($load(ptr!(src, i)), $load(ptr!(add, i)));
+                let v = $sub($sub(v, $load(ptr!(sub1, i))), $load(ptr!(sub2, i)));
+                $store(ptr_mut!(dst, i), v);
+            }
+        }
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn add2_sub2(
+            src: &Aligned,
+            dst: &mut Aligned,
+            adds: [&Aligned; 2],
+            subs: [&Aligned; 2],
+        ) {
+            for i in (0..HIDDEN_SIZE).step_by(LANES) {
+                let v = $add(
+                    $add($load(ptr!(src, i)), $load(ptr!(adds[0], i))),
+                    $load(ptr!(adds[1], i)),
+                );
+                let v = $sub($sub(v, $load(ptr!(subs[0], i))), $load(ptr!(subs[1], i)));
+                $store(ptr_mut!(dst, i), v);
+            }
+        }
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn crelu_dot(acc: &Aligned, w: &Aligned) -> i32 {
+            let zero = $zero();
+            let qa = $splat(QA as i16);
+            let mut sum = $zero();
+            for i in (0..HIDDEN_SIZE).step_by(LANES) {
+                let v = $min($max($load(ptr!(acc, i)), zero), qa);
+                // 255 * i16::MAX * 2 fits comfortably in an i32 lane
+                sum = $add32(sum, $madd(v, $load(ptr!(w, i))));
+            }
+
+            let mut lanes = [0i32; LANES / 2];
+            $store(lanes.as_mut_ptr().cast(), sum);
+            lanes.iter().sum()
+        }
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn screlu_dot(acc: &Aligned, w: &Aligned) -> i32 {
+            let zero = $zero();
+            let qa = $splat(QA as i16);
+            let mut sum = $zero();
+            for i in (0..HIDDEN_SIZE).step_by(LANES) {
+                let v = $min($max($load(ptr!(acc, i)), zero), qa);
+                // v * w fits in an i16 for i8 range weights, then madd squares v in i32
+                sum = $add32(sum, $madd(v, $mullo(v, $load(ptr!(w, i)))));
+            }
+
+            let mut lanes = [0i32; LANES / 2];
+            $store(lanes.as_mut_ptr().cast(), sum);
+            lanes.iter().sum()
+        }
+
+        #[target_feature(enable = $feature)]
+        pub unsafe fn pairwise_dot(acc: &Aligned, w: &Aligned) -> i32 {
+            let zero = $zero();
+            let qa = $splat(QA as i16);
+            let mut sum = $zero();
+            for i in (0..HIDDEN_SIZE / 2).step_by(LANES) {
+                let l = $min($max($load(ptr!(acc, i)), zero), qa);
+                let r = $min($max($load(ptr!(acc, i + HIDDEN_SIZE / 2)), zero), qa);
+                sum = $add32(sum, $madd(l, $mullo(r, $load(ptr!(w, i)))));
+            }
+
+            let mut lanes = [0i32; LANES / 2];
+            $store(lanes.as_mut_ptr().cast(), sum);
+            lanes.iter().sum()
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse41 {
+    simd_kernels! {
+        feature: "sse4.1",
+        vector: __m128i,
+        zero: _mm_setzero_si128,
+        splat: _mm_set1_epi16,
+        load: _mm_loadu_si128,
+        store: _mm_storeu_si128,
+        add: _mm_add_epi16,
+        sub: _mm_sub_epi16,
+        max: _mm_max_epi16,
+        min: _mm_min_epi16,
+        mullo: _mm_mullo_epi16,
+        madd: _mm_madd_epi16,
+        add32: _mm_add_epi32,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    simd_kernels! {
+        feature: "avx2",
+        vector: __m256i,
+        zero: _mm256_setzero_si256,
+        splat: _mm256_set1_epi16,
+        load: _mm256_loadu_si256,
+        store: _mm256_storeu_si256,
+        add: _mm256_add_epi16,
+        sub: _mm256_sub_epi16,
+        max: _mm256_max_epi16,
+        min: _mm256_min_epi16,
+        mullo: _mm256_mullo_epi16,
+        madd: _mm256_madd_epi16,
+        add32: _mm256_add_epi32,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    simd_kernels! {
+        feature: "avx512f,avx512bw",
+        vector: __m512i,
+        zero: _mm512_setzero_si512,
+        splat: _mm512_set1_epi16,
+        load: _mm512_loadu_si512,
+        store: _mm512_storeu_si512,
+        add: _mm512_add_epi16,
+        sub: _mm512_sub_epi16,
+        max: _mm512_max_epi16,
+        min: _mm512_min_epi16,
+        mullo: _mm512_mullo_epi16,
+        madd: _mm512_madd_epi16,
+        add32: _mm512_add_epi32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{nnue::NNUE, rng::Rng};
+
+    use super::{scalar, SimdLevel};
+
+    #[test]
+    fn test_simd_kernels_match_scalar() {
+        let mut rng = Rng::new(3);
+        let mut row = || &NNUE.feature_weights[rng.below(768)];
+
+        // accumulators well outside the crelu range in both directions
+        let mut src = NNUE.feature_bias;
+        for (i, v) in src.iter_mut().enumerate() {
+            *v = (i as i16 - 32) * 37;
+        }
+
+        // SCReLU and pairwise need weights in the i8 range
+        let mut i8_weights = NNUE.output_weights[0];
+        for w in i8_weights.iter_mut() {
+            *w = (*w).clamp(i8::MIN.into(), i8::MAX.into());
+        }
+
+        for level in SimdLevel::available() {
+            for _ in 0..200 {
+                let [w1, w2, w3, w4] = [row(), row(), row(), row()];
+
+                let (mut expected, mut found) = (src, src);
+                scalar::add(&mut expected, w1);
+                dispatch!(level, add(&mut found, w1));
+                assert_eq!(expected, found, "add {level:?}");
+
+                scalar::sub(&mut expected, w2);
+                dispatch!(level, sub(&mut found, w2));
+                assert_eq!(expected, found, "sub {level:?}");
+
+                let (mut expected, mut found) = (src, src);
+                scalar::add_sub(&src, &mut expected, w1, w2);
+                dispatch!(level, add_sub(&src, &mut found, w1, w2));
+                assert_eq!(expected, found, "add_sub {level:?}");
+
+                scalar::add_sub2(&src, &mut expected, w1, w2, w3);
+                dispatch!(level, add_sub2(&src, &mut found, w1, w2, w3));
+                assert_eq!(expected, found, "add_sub2 {level:?}");
+
+                scalar::add2_sub2(&src, &mut expected, [w1, w2], [w3, w4]);
+                dispatch!(level, add2_sub2(&src, &mut found, [w1, w2], [w3, w4]));
+                assert_eq!(expected, found, "add2_sub2 {level:?}");
+
+                let expected = scalar::crelu_dot(&found, &NNUE.output_weights[0]);
+                let eval = dispatch!(level, crelu_dot(&found, &NNUE.output_weights[0]));
+                assert_eq!(expected, eval, "crelu_dot {level:?}");
+
+                let expected = scalar::screlu_dot(&found, &i8_weights);
+                let eval = dispatch!(level, screlu_dot(&found, &i8_weights));
+                assert_eq!(expected, eval, "screlu_dot {level:?}");
+
+                let expected = scalar::pairwise_dot(&found, &i8_weights);
+                let eval = dispatch!(level, pairwise_dot(&found, &i8_weights));
+                assert_eq!(expected, eval, "pairwise_dot {level:?}");
+
+                src = found;
+            }
+        }
+    }
+}
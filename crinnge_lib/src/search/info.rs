@@ -1,13 +1,12 @@
 use std::{
-    sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
-        mpsc::Receiver,
-        Mutex,
-    },
-    time::Instant,
+    slice,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
+    moves::PrincipalVariation,
     search::{MATE_SCORE, MAX_DEPTH},
     thread_data::ThreadData,
     timeman::TimeManager,
@@ -25,7 +24,7 @@ pub struct SearchInfo<'a> {
     pub local_nodes: u64,
     pub node_buffer: u64,
     pub seldepth: usize,
-    pub stdin: Option<&'a Mutex<Receiver<String>>>,
+    pub ponderhit: Option<&'a AtomicBool>,
     pub stdout: bool,
     pub options: SearchOptions,
     #[cfg(feature = "stats")]
@@ -46,7 +45,7 @@ impl<'a> SearchInfo<'a> {
             local_nodes: 0,
             node_buffer: 0,
             seldepth: 0,
-            stdin: None,
+            ponderhit: None,
             stdout: true,
             options: SearchOptions::default(),
 
@@ -64,8 +63,8 @@ impl<'a> SearchInfo<'a> {
         self
     }
 
-    pub fn stdin(mut self, stdin: Option<&'a Mutex<Receiver<String>>>) -> Self {
-        self.stdin = stdin;
+    pub fn ponderhit(mut self, ponderhit: Option<&'a AtomicBool>) -> Self {
+        self.ponderhit = ponderhit;
         self
     }
 
@@ -100,27 +99,38 @@ impl<'a> SearchInfo<'a> {
         self.local_nodes + self.node_buffer
     }
 
-    pub fn stopped<M: ThreadType>(&self) -> bool {
+    pub fn stopped<M: ThreadType>(&mut self) -> bool {
         let signal = self.stopped.load(Ordering::Relaxed);
         if signal {
             return true;
         }
-        // check for UCI stop/quit in the main thread
-        if M::MAIN_THREAD && self.stdin.is_some() {
-            let signal = self
-                .stdin
-                .unwrap()
-                .lock()
-                .unwrap()
-                .try_recv()
-                .map_or(false, |s| s.starts_with("stop"));
-            if signal || UCI_QUIT.load(Ordering::Relaxed) {
+        // check for UCI ponderhit/quit in the main thread
+        if M::MAIN_THREAD {
+            if self.time_manager.pondering() && self.ponderhit_received() {
+                self.time_manager.ponderhit();
+            }
+            if UCI_QUIT.load(Ordering::Relaxed) {
                 self.stop();
             }
         }
         false
     }
 
+    /// UCI doesn't allow bestmove to be sent while pondering, so wait for ponderhit or stop
+    pub fn wait_while_pondering(&mut self) {
+        while self.time_manager.pondering() && !UCI_QUIT.load(Ordering::Relaxed) {
+            if self.ponderhit_received() || self.ponderhit.is_none() {
+                self.time_manager.ponderhit();
+            } else {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    fn ponderhit_received(&self) -> bool {
+        self.ponderhit.is_some_and(|p| p.load(Ordering::Relaxed))
+    }
+
     pub fn stop(&self) {
         self.stopped.store(true, Ordering::Relaxed);
     }
@@ -130,32 +140,41 @@ impl<'a> SearchInfo<'a> {
             let nodes = self.global_node_count();
             let elapsed = self.time_manager.elapsed().as_millis() as u64;
             let nps = nodes * 1_000 / elapsed.max(1);
+            let hash_fill = t.tt.fill();
 
-            let mate_plies = MATE_SCORE - t.root_score.abs();
-            let score_string = if mate_plies <= MAX_DEPTH {
-                format!(
-                    "mate {}{}",
-                    if t.root_score > 0 { "" } else { "-" },
-                    (mate_plies + 1) / 2
-                )
+            // report the best line even if no depth has been completed yet
+            let best = (t.root_score, t.pv);
+            let lines = if t.multi_pvs.is_empty() {
+                slice::from_ref(&best)
             } else {
-                format!("cp {}", t.root_score)
+                &t.multi_pvs[..]
             };
 
-            let hash_fill = t.tt.fill();
-
-            println!(
-                "info depth {} seldepth {} score {} nodes {} nps {} hashfull {} time {} pv {}",
-                depth, self.seldepth, score_string, nodes, nps, hash_fill, elapsed, t.pv
-            );
+            for (i, (score, pv)) in lines.iter().enumerate() {
+                println!(
+                    "info depth {} seldepth {} multipv {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+                    depth,
+                    self.seldepth,
+                    i + 1,
+                    score_string(*score),
+                    nodes,
+                    nps,
+                    hash_fill,
+                    elapsed,
+                    pv
+                );
+            }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn print_aw_fail_report<M: ThreadType>(
         &self,
         depth: i32,
+        pv_index: usize,
         score: i32,
         score_type: ScoreType,
+        pv: &PrincipalVariation,
         t: &ThreadData,
     ) {
         if M::MAIN_THREAD && self.stdout {
@@ -163,17 +182,6 @@ impl<'a> SearchInfo<'a> {
             let elapsed = self.time_manager.elapsed().as_millis() as u64;
             let nps = nodes * 1_000 / elapsed.max(1);
 
-            let mate_plies = MATE_SCORE - score.abs();
-            let score_string = if mate_plies <= MAX_DEPTH {
-                format!(
-                    "mate {}{}",
-                    if score > 0 { "" } else { "-" },
-                    (mate_plies + 1) / 2
-                )
-            } else {
-                format!("cp {}", score)
-            };
-
             let score_bound = match score_type {
                 ScoreType::Exact => "",
                 ScoreType::LowerBound => " lowerbound",
@@ -183,16 +191,17 @@ impl<'a> SearchInfo<'a> {
             let hash_fill = t.tt.fill();
 
             println!(
-                "info depth {} seldepth {} score {}{} nodes {} nps {} hashfull {} time {} pv {}",
+                "info depth {} seldepth {} multipv {} score {}{} nodes {} nps {} hashfull {} time {} pv {}",
                 depth,
                 self.seldepth,
-                score_string,
+                pv_index + 1,
+                score_string(score),
                 score_bound,
                 nodes,
                 nps,
                 hash_fill,
                 elapsed,
-                t.pv
+                pv
             );
         }
     }
@@ -211,3 +220,16 @@ impl<'a> SearchInfo<'a> {
         );
     }
 }
+
+fn score_string(score: i32) -> String {
+    let mate_plies = MATE_SCORE - score.abs();
+    if mate_plies <= MAX_DEPTH {
+        format!(
+            "mate {}{}",
+            if score > 0 { "" } else { "-" },
+            (mate_plies + 1) / 2
+        )
+    } else {
+        format!("cp {}", score)
+    }
+}
@@ -1,10 +1,11 @@
 pub mod info;
 pub mod options;
 
+use std::cmp::Reverse;
 use std::sync::atomic::Ordering;
 use std::thread;
 
-use crinnge_pregen::LMR;
+use crinnge_pregen::{LMP_MARGINS, LMR};
 use info::SearchInfo;
 
 use crate::move_sorting::{MoveGenStage, MoveSorter};
@@ -49,12 +50,22 @@ impl NodeType for NonRoot {
 }
 
 impl Board {
+    /// The stop signal isn't cleared here so that a stop sent before the search starts isn't lost
     pub fn search(
         &self,
         info: &mut SearchInfo,
         threads_data: &mut [ThreadData],
     ) -> (i32, Option<Move>) {
-        let legals = self.legal_moves();
+        // clear leftover PVs and MultiPV exclusions from previous searches before they can
+        // filter the root moves
+        for t in threads_data.iter_mut() {
+            t.reset();
+        }
+
+        let mut legals = self.legal_moves();
+        if let Some(t) = threads_data.first() {
+            legals.retain(|&mv| !t.skip_root_move(mv));
+        }
         if legals.is_empty() {
             return (0, None);
         }
@@ -66,12 +77,6 @@ impl Board {
         info.global_nodes.store(0, Ordering::Relaxed);
         info.local_nodes = 0;
         info.node_buffer = 0;
-        info.stopped.store(false, Ordering::Relaxed);
-
-        // clear leftover PVs from previous searches
-        for t in threads_data.iter_mut() {
-            t.reset();
-        }
 
         let (t1, rest) = threads_data.split_first_mut().unwrap();
         thread::scope(|s| {
@@ -103,37 +108,85 @@ impl Board {
         }
 
         let best_move = *best_thread.pv.first().unwrap_or_else(|| &legals[0]);
+        let ponder_move = self.ponder_move(best_move, best_thread);
+
+        info.wait_while_pondering();
 
         // reporting to stdout
         if info.stdout {
             #[cfg(feature = "stats")]
             info.print_stats(best_thread.depth_reached);
-            println!("bestmove {}", best_move.coords());
+            if let Some(ponder_move) = ponder_move {
+                println!(
+                    "bestmove {} ponder {}",
+                    best_move.coords(),
+                    ponder_move.coords()
+                );
+            } else {
+                println!("bestmove {}", best_move.coords());
+            }
         }
 
         (best_thread.root_score, Some(best_move))
     }
 
+    /// The expected reply to the best move, from the PV or the TT if the PV is too short
+    fn ponder_move(&self, best_move: Move, t: &ThreadData) -> Option<Move> {
+        if let Some(&mv) = t.pv.get(1) {
+            return Some(mv);
+        }
+
+        let mut next = *self;
+        if !next.make_move_only(best_move) {
+            return None;
+        }
+        let mv = t.tt.get(next.hash())?.best_move;
+
+        (next.is_pseudolegal(mv) && next.make_move_only(mv)).then_some(mv)
+    }
+
     fn iterative_deepening<M: ThreadType>(&self, info: &mut SearchInfo, t: &mut ThreadData) {
-        let mut window = AspirationWindow::default();
-        for i in 1..MAX_DEPTH {
-            if i > 1 {
-                window = AspirationWindow::new_around(t.root_score, info.options.asp_window_init);
-            }
-            let mut pv = PrincipalVariation::new();
-            let score = self.aspiration_window::<M>(&mut pv, info, t, &mut window, i);
+        // can't search more lines than there are legal moves
+        let root_moves = self.legal_moves();
+        let root_moves = root_moves.iter().filter(|&&mv| !t.skip_root_move(mv));
+        let multi_pv = info.options.multi_pv.min(root_moves.count());
+        'deepening: for i in 1..MAX_DEPTH {
+            t.excluded_root_moves.clear();
+            let mut lines = Vec::with_capacity(multi_pv);
+
+            for pv_index in 0..multi_pv {
+                let mut window = match t.multi_pvs.get(pv_index) {
+                    Some(&(score, _)) if i > 1 => {
+                        AspirationWindow::new_around(score, info.options.asp_window_init)
+                    }
+                    _ => AspirationWindow::default(),
+                };
+                let mut pv = PrincipalVariation::new();
+                let score = self.aspiration_window::<M>(&mut pv, info, t, &mut window, i, pv_index);
 
-            // fixed time, hard time limit or node limit reached somewhere in the main thread
-            if info.stopped::<M>() {
-                // can't trust results from a partial search, but report accurate statistics for node-determinism
-                info.print_depth_report::<M>(t, i);
-                break;
+                // fixed time, hard time limit or node limit reached somewhere in the main thread
+                if info.stopped::<M>() {
+                    // can't trust results from a partial search, but report accurate statistics for node-determinism
+                    info.print_depth_report::<M>(t, i);
+                    break 'deepening;
+                }
+
+                lines.push((score, pv));
+
+                // search the next line without the moves already found
+                let Some(&mv) = pv.first() else {
+                    break;
+                };
+                t.excluded_root_moves.push(mv);
             }
 
+            // later lines can score higher than earlier ones, report them in rank order
+            lines.sort_by_key(|&(score, _)| Reverse(score));
+
             // update thread data for depth report
-            t.root_score = score;
+            (t.root_score, t.pv) = lines[0];
             t.depth_reached = i;
-            t.pv = pv;
+            t.multi_pvs = lines;
 
             info.print_depth_report::<M>(t, i);
 
@@ -143,6 +196,12 @@ impl Board {
                 break;
             }
 
+            // a mate short enough has been found, stop all threads
+            if info.time_manager.mate_limit_reached(t.root_score) {
+                info.stop();
+                break;
+            }
+
             // check time and node conditions in the main thread
             if M::MAIN_THREAD
                 && (info.time_manager.soft_time_limit_reached()
@@ -157,6 +216,7 @@ impl Board {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn aspiration_window<M: ThreadType>(
         &self,
         pv: &mut PrincipalVariation,
@@ -164,6 +224,7 @@ impl Board {
         t: &mut ThreadData,
         window: &mut AspirationWindow,
         depth: i32,
+        pv_index: usize,
     ) -> i32 {
         loop {
             let score = self.negamax::<Root, M>(pv, info, t, window.lower, window.upper, depth, 0);
@@ -187,7 +248,7 @@ impl Board {
                 ScoreType::Exact => return score,
             }
 
-            info.print_aw_fail_report::<M>(depth, score, score_type, t);
+            info.print_aw_fail_report::<M>(depth, pv_index, score, score_type, pv, t);
         }
     }
 
@@ -253,6 +314,13 @@ impl Board {
         }
 
         let pv_node = alpha != beta - 1;
+        // a singular extension search of this node leaves out the TT move
+        let excluded_move = t.excluded_moves[ply];
+        let singular_search = !excluded_move.is_null();
+
+        if R::ROOT {
+            t.double_extensions[ply] = 0;
+        }
 
         // probe TT
         let mut tt_move = None;
@@ -262,7 +330,10 @@ impl Board {
             {
                 info.tt_hits += 1;
             }
-            if !pv_node && entry.depth as i32 >= depth && entry.score_beats_bounds(alpha, beta, ply)
+            if !pv_node
+                && !singular_search
+                && entry.depth as i32 >= depth
+                && entry.score_beats_bounds(alpha, beta, ply)
             {
                 pv.clear();
                 return entry.score.get(ply);
@@ -278,9 +349,7 @@ impl Board {
         if !R::ROOT
             && !pv_node
             && depth >= info.options.iir_min_depth
-            && tt_entry.map_or(true, |e| {
-                e.depth as i32 <= depth - info.options.iir_tt_depth_margin
-            })
+            && tt_entry.is_none_or(|e| e.depth as i32 <= depth - info.options.iir_tt_depth_margin)
         {
             depth -= 1;
         }
@@ -288,12 +357,18 @@ impl Board {
         let mut line = PrincipalVariation::new();
         let in_check = self.in_check();
 
-        let mut eval = if in_check {
+        let raw_eval = if in_check {
             // static eval isn't valid while in check
             -INF
         } else {
             self.evaluate(t, ply)
         };
+        let static_eval = if in_check {
+            raw_eval
+        } else {
+            t.correct_eval(self, raw_eval)
+        };
+        let mut eval = static_eval;
 
         // use TT score as static eval if the bounds work
         if let Some(entry) = tt_entry {
@@ -305,21 +380,37 @@ impl Board {
         // store the current static eval for use with heuristics down the tree
         t.evals[ply] = eval;
 
-        // TODO: improving
+        // the position is improving if the static eval has gone up since our last move, looking
+        // further back if we were in check then. Without an earlier eval assume it is improving
+        let improving = if in_check {
+            false
+        } else if ply >= 2 && t.evals[ply - 2] != -INF {
+            eval > t.evals[ply - 2]
+        } else if ply >= 4 && t.evals[ply - 4] != -INF {
+            eval > t.evals[ply - 4]
+        } else {
+            true
+        };
 
         // Whole-node pruning techniques to hopefully prune before movegen and search
-        if !R::ROOT && !pv_node && !in_check {
+        if !R::ROOT && !pv_node && !in_check && !singular_search {
             // Reverse Futility Pruning: if the static eval is high enough above beta,
             // assume we can skip search
-            if depth < info.options.rfp_max_depth
-                && (eval - depth * info.options.rfp_margin) >= beta
-            {
-                // TODO: RFP improving margin
-                return eval - depth * info.options.rfp_margin;
-            }
-            // TODO: nmp only when static eval >= beta
+            // An improving position is more likely to stay above beta, so needs less margin,
+            // but never a negative one that would return more than the static eval
+            let rfp_margin = (depth * info.options.rfp_margin
+                - improving as i32 * info.options.rfp_improving_margin)
+                .max(0);
+            if depth < info.options.rfp_max_depth && (eval - rfp_margin) >= beta {
+                return eval - rfp_margin;
+            }
+
+            // Null Move Pruning: if passing still beats beta then a real move very likely will.
+            // Only try it when the static eval is already above beta, by a margin if not improving
+            let nmp_margin = !improving as i32 * info.options.nmp_improving_margin;
             if t.nmp_enabled
                 && depth >= info.options.nmp_min_depth
+                && eval >= beta + nmp_margin
                 && self.has_non_pawns(self.player())
             {
                 let c = info.options.nmp_r_const;
@@ -361,19 +452,41 @@ impl Board {
         }
 
         let [mut noisy, mut quiet] = [MoveList::new(); 2];
-        let mut move_sorter = MoveSorter::new(tt_move, &mut noisy, &mut quiet);
+        let mut move_sorter = MoveSorter::new(tt_move, ply, &mut noisy, &mut quiet)
+            .refutations(t.killers[ply], t.counter_move(ply));
 
         let old_alpha = alpha;
         let mut best_score = -INF;
         let mut best_move = None;
         let mut moves_searched = 0;
         let mut quiets_tried = MoveList::new();
+        let mut captures_tried = MoveList::new();
 
         t.search_history.push(self.hash());
         while let Some((mv, _)) = move_sorter.next(self, t) {
+            // skip root moves not in UCI searchmoves or already reported in an earlier MultiPV line
+            if R::ROOT && t.skip_root_move(mv) {
+                continue;
+            }
+            if mv == excluded_move {
+                continue;
+            }
             let capture = self.is_capture(mv);
             // Move-based pruning techniques, used only after the first move is searched
             if moves_searched > 0 {
+                // Late Move Pruning: skip quiets ordered late at low depth, they rarely beat alpha
+                if !R::ROOT
+                    && !pv_node
+                    && !in_check
+                    && !capture
+                    && mv.promo().is_none()
+                    && depth <= info.options.lmp_max_depth
+                    && best_score > -MIN_TB_WIN_SCORE
+                    && moves_searched >= LMP_MARGINS[depth as usize][improving as usize]
+                {
+                    continue;
+                }
+
                 // SEE pruning: if this move loses too much material at low depth then skip it
                 if !R::ROOT && !pv_node && depth < info.options.see_pruning_max_depth {
                     let threshold = depth
@@ -390,12 +503,77 @@ impl Board {
                 }
             }
 
+            let mut extension = 0;
+            // Singular Extensions: if every other move fails low against a margin below the TT
+            // score then the TT move is singular and worth searching deeper
+            let singular_candidate = tt_entry.filter(|entry| {
+                !R::ROOT
+                    && !singular_search
+                    && Some(mv) == tt_move
+                    && depth >= info.options.se_min_depth
+                    && entry.depth as i32 >= depth - info.options.se_tt_depth_margin
+                    && entry.info.score_type() != ScoreType::UpperBound
+                    && entry.score.get(ply).abs() < MIN_TB_WIN_SCORE
+            });
+            if let Some(entry) = singular_candidate {
+                let tt_score = entry.score.get(ply);
+                let s_beta = tt_score - depth * info.options.se_beta_margin;
+                let s_depth = (depth - 1) / 2;
+
+                // the excluded search is of this same position, so it can't be in the history
+                t.search_history.pop();
+                t.excluded_moves[ply] = mv;
+                let score = self.negamax::<NonRoot, M>(
+                    &mut line,
+                    info,
+                    t,
+                    s_beta - 1,
+                    s_beta,
+                    s_depth,
+                    ply,
+                );
+                t.excluded_moves[ply] = Move::NULL;
+
+                if info.stopped::<M>() {
+                    pv.clear();
+                    return 0;
+                }
+
+                if score < s_beta {
+                    extension = 1;
+                    // extend further when the TT move is far better than the alternatives,
+                    // limiting how often this can happen along one line
+                    if !pv_node
+                        && t.double_extensions[ply] < info.options.se_max_double_extensions
+                        && score < s_beta - info.options.se_double_margin
+                    {
+                        extension = 2;
+                        if !capture && score < s_beta - info.options.se_triple_margin {
+                            extension = 3;
+                        }
+                    }
+                } else if s_beta >= beta {
+                    // Multi-Cut: another move also beats beta, so this node is very likely to
+                    // fail high
+                    return s_beta;
+                } else if tt_score >= beta {
+                    // Negative Extension: the TT move isn't singular but some other move is
+                    // still likely to cut, so spend less effort on it
+                    extension = -1;
+                }
+                t.search_history.push(self.hash());
+            }
+            let new_depth = depth - 1 + extension;
+            t.double_extensions[ply + 1] = t.double_extensions[ply] + (extension > 1) as i32;
+
             let mut new = *self;
 
             if !new.make_move_nnue(mv, t, ply) {
                 continue;
             }
-            if !capture {
+            if capture {
+                captures_tried.push(mv);
+            } else {
                 quiets_tried.push(mv);
             }
 
@@ -409,9 +587,17 @@ impl Board {
                 if !capture && mv.promo().is_none() {
                     // Late Move Reductions: moves ordered later are more likely to fail with less searching
                     r += LMR[(depth as usize).min(63)][moves_searched.min(63)] as i32;
+
+                    // reduce moves with good history less and moves with bad history more
+                    let piece = self.piece_on(mv.from()).unwrap();
+                    r -= t.quiet_history(ply, piece, mv.to()) / info.options.lmr_history_divisor;
+
+                    // reduce more when the position is getting worse
+                    r += !improving as i32 * info.options.lmr_improving_reduction;
+                    r = r.clamp(0, new_depth.max(1) - 1);
                 }
 
-                let r_depth = depth - 1 - r;
+                let r_depth = new_depth - r;
                 score = -new.negamax::<NonRoot, M>(
                     &mut line,
                     info,
@@ -439,7 +625,7 @@ impl Board {
                     t,
                     -alpha - 1,
                     -alpha,
-                    depth - 1,
+                    new_depth,
                     ply + 1,
                 );
             }
@@ -458,7 +644,7 @@ impl Board {
                     t,
                     -beta,
                     -alpha,
-                    depth - 1,
+                    new_depth,
                     ply + 1,
                 );
             }
@@ -489,8 +675,12 @@ impl Board {
         t.search_history.pop();
 
         if moves_searched == 0 {
-            // no legal moves, checkmate or stalemate
             pv.clear();
+            // only the excluded move was legal, so it is singular
+            if singular_search {
+                return alpha;
+            }
+            // no legal moves, checkmate or stalemate
             if in_check {
                 return -(MATE_SCORE - ply as i32);
             } else {
@@ -505,9 +695,18 @@ impl Board {
 
             // update quiet histories if a quiet is best
             if !self.is_capture(best_move) {
-                t.update_quiet_histories(self, depth, best_move, &quiets_tried);
+                t.update_quiet_histories(self, ply, depth, best_move, &quiets_tried);
+
+                // remember quiets that refute this position for its siblings
+                if best_score >= beta && best_move.promo().is_none() {
+                    t.update_refutations(ply, best_move);
+                }
             }
         }
+        if best_score >= beta {
+            // captures that didn't cause the cutoff are punished even if a quiet did
+            t.update_capture_histories(self, depth, best_move, &captures_tried);
+        }
 
         // store search results in TT
         let score_type = if best_score >= beta {
@@ -523,7 +722,26 @@ impl Board {
             ScoreType::UpperBound
         };
 
-        t.tt.store(self.hash(), best_score, score_type, best_move, depth, ply);
+        // learn how far the static eval was from the search result, when the bound says something
+        // about it and a capture didn't decide the score
+        let bound_informative = match score_type {
+            ScoreType::LowerBound => best_score > static_eval,
+            ScoreType::UpperBound => best_score < static_eval,
+            ScoreType::Exact => true,
+        };
+        if !in_check
+            && !singular_search
+            && bound_informative
+            && !self.is_capture(best_move)
+            && best_score.abs() < MIN_TB_WIN_SCORE
+        {
+            t.update_correction_history(self, depth, best_score - raw_eval);
+        }
+
+        // searches with restricted root moves or an excluded move may not find the true best move
+        if (!R::ROOT || !t.root_moves_restricted()) && !singular_search {
+            t.tt.store(self.hash(), best_score, score_type, best_move, depth, ply);
+        }
 
         best_score
     }
@@ -609,7 +827,7 @@ impl Board {
         let mut line = PrincipalVariation::new();
 
         let [mut noisy, mut quiet] = [MoveList::new(); 2];
-        let mut move_sorter = MoveSorter::new(tt_move, &mut noisy, &mut quiet).noisy_only();
+        let mut move_sorter = MoveSorter::new(tt_move, ply, &mut noisy, &mut quiet).noisy_only();
 
         let mut best_move = None;
         let mut best_score = eval;
@@ -698,3 +916,33 @@ fn randomize_draw_score(_info: &SearchInfo) -> i32 {
     // 4 - (info.global_node_count() as i32 & 7)
     0
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU64};
+    use std::time::Instant;
+
+    use crate::{board::Board, thread_data::ThreadData, timeman::TimeManager, tt::TT};
+
+    use super::info::SearchInfo;
+
+    #[test]
+    fn test_repeated_search_single_legal_move() {
+        let board = Board::from_fen("7k/8/8/8/8/8/6q1/K7 w - - 0 1").unwrap();
+        let tt = TT::new(1);
+        let mut threads_data = vec![ThreadData::new(&board, tt.slice())];
+        let (stop, nodes) = (AtomicBool::new(false), AtomicU64::new(0));
+
+        // the first search excludes its best move for a second MultiPV line, which must not
+        // leak into the next search
+        for _ in 0..2 {
+            let time_manager = TimeManager::new(Instant::now()).fixed_depth(Some(3));
+            let mut info = SearchInfo::new(&stop, &nodes)
+                .time_manager(time_manager)
+                .stdout(false);
+            board.refresh_root_accumulator(&mut threads_data[0]);
+            let (_, best_move) = board.search(&mut info, &mut threads_data);
+            assert_eq!(best_move.map(|mv| mv.coords()), Some(String::from("a1b1")));
+        }
+    }
+}
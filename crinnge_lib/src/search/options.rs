@@ -6,6 +6,7 @@ use crate::timeman::TimeOptions;
 pub struct SearchOptions {
     pub threads: usize,
     pub hash: usize,
+    pub multi_pv: usize,
     pub asp_window_init: i32,
     pub asp_window_scale_percent: i32,
     pub hard_time_percent: i64,
@@ -16,11 +17,22 @@ pub struct SearchOptions {
     pub nmp_r_depth_divisor: i32,
     pub rfp_max_depth: i32,
     pub rfp_margin: i32,
+    pub rfp_improving_margin: i32,
+    pub nmp_improving_margin: i32,
+    pub lmr_improving_reduction: i32,
     pub iir_min_depth: i32,
     pub iir_tt_depth_margin: i32,
     pub see_pruning_max_depth: i32,
     pub see_capture_margin: i32,
     pub see_quiet_margin: i32,
+    pub lmr_history_divisor: i32,
+    pub se_min_depth: i32,
+    pub se_tt_depth_margin: i32,
+    pub se_beta_margin: i32,
+    pub se_double_margin: i32,
+    pub se_triple_margin: i32,
+    pub se_max_double_extensions: i32,
+    pub lmp_max_depth: i32,
 }
 
 impl SearchOptions {
@@ -38,6 +50,7 @@ impl Default for SearchOptions {
         Self {
             threads: 1,
             hash: 8,
+            multi_pv: 1,
             asp_window_init: 40,
             asp_window_scale_percent: 200,
             hard_time_percent: 50,
@@ -48,11 +61,22 @@ impl Default for SearchOptions {
             nmp_r_depth_divisor: 3,
             rfp_max_depth: 16,
             rfp_margin: 38,
+            rfp_improving_margin: 38,
+            nmp_improving_margin: 16,
+            lmr_improving_reduction: 1,
             iir_min_depth: 4,
             iir_tt_depth_margin: 128, // high value effectively disables this feature
             see_pruning_max_depth: 10,
             see_capture_margin: -54,
             see_quiet_margin: -45,
+            lmr_history_divisor: 8192,
+            se_min_depth: 8,
+            se_tt_depth_margin: 3,
+            se_beta_margin: 2,
+            se_double_margin: 20,
+            se_triple_margin: 80,
+            se_max_double_extensions: 6,
+            lmp_max_depth: 8,
         }
     }
 }
@@ -62,6 +86,10 @@ impl Display for SearchOptions {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "option name Threads type spin default {} min 1 max 999", self.threads)?;
         writeln!(f, "option name Hash type spin default {} min 1 max 999", self.hash)?;
+        writeln!(f, "option name MultiPV type spin default {} min 1 max 218", self.multi_pv)?;
+        writeln!(f, "option name EvalFile type string default <default>")?;
+        writeln!(f, "option name Ponder type check default false")?;
+        writeln!(f, "option name UCI_Chess960 type check default false")?;
         #[cfg(feature = "tuning")] {
         use super::{INF, MAX_DEPTH};
         writeln!(f, "option name AspWindowInit type spin default {} min 1 max {}", self.asp_window_init, INF)?;
@@ -74,11 +102,22 @@ impl Display for SearchOptions {
         writeln!(f, "option name NmpReductionDepthDivisor type spin default {} min 1 max {}", self.nmp_r_const, MAX_DEPTH)?;
         writeln!(f, "option name RfpMaxDepth type spin default {} min 1 max {}", self.rfp_max_depth, MAX_DEPTH)?;
         writeln!(f, "option name RfpMargin type spin default {} min 1 max {}", self.rfp_margin, INF)?;
+        writeln!(f, "option name RfpImprovingMargin type spin default {} min 0 max {}", self.rfp_improving_margin, INF)?;
+        writeln!(f, "option name NmpImprovingMargin type spin default {} min 0 max {}", self.nmp_improving_margin, INF)?;
+        writeln!(f, "option name LmrImprovingReduction type spin default {} min 0 max {}", self.lmr_improving_reduction, MAX_DEPTH)?;
         writeln!(f, "option name IirMinDepth type spin default {} min 1 max {}", self.iir_min_depth, MAX_DEPTH)?;
         writeln!(f, "option name IirTtDepthMargin type spin default {} min 1 max {}", self.iir_tt_depth_margin, MAX_DEPTH)?;
         writeln!(f, "option name SeePruningMaxDepth type spin default {} min 1 max {}", self.see_pruning_max_depth, MAX_DEPTH)?;
         writeln!(f, "option name SeeCaptureMargin type spin default {} min -100 max 100", self.see_capture_margin)?;
         writeln!(f, "option name SeeQuietMargin type spin default {} min -100 max 100", self.see_quiet_margin)?;
+        writeln!(f, "option name LmrHistoryDivisor type spin default {} min 1 max 65536", self.lmr_history_divisor)?;
+        writeln!(f, "option name SeMinDepth type spin default {} min 1 max {}", self.se_min_depth, MAX_DEPTH)?;
+        writeln!(f, "option name SeTtDepthMargin type spin default {} min 0 max {}", self.se_tt_depth_margin, MAX_DEPTH)?;
+        writeln!(f, "option name SeBetaMargin type spin default {} min 0 max 100", self.se_beta_margin)?;
+        writeln!(f, "option name SeDoubleMargin type spin default {} min 0 max 1000", self.se_double_margin)?;
+        writeln!(f, "option name SeTripleMargin type spin default {} min 0 max 1000", self.se_triple_margin)?;
+        writeln!(f, "option name SeMaxDoubleExtensions type spin default {} min 0 max {}", self.se_max_double_extensions, MAX_DEPTH)?;
+        writeln!(f, "option name LmpMaxDepth type spin default {} min 0 max 31", self.lmp_max_depth)?;
         }
         Ok(())
     }
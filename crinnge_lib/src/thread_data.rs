@@ -1,80 +1,217 @@
+use crinnge_bitboards::Square;
+
 use crate::{
     board::Board,
     historytables::*,
     moves::{Move, MoveList, PrincipalVariation},
-    nnue::Accumulator,
-    search::MAX_DEPTH,
+    nnue::{network, AccumulatorEntry, FinnyTable, NetworkRef},
+    search::{MAX_DEPTH, MIN_TB_WIN_SCORE},
     tt::TTSlice,
+    types::*,
 };
 
+/// How many plies back continuation histories look
+pub const CONT_HIST_PLIES: usize = 2;
+
 #[derive(Clone, Debug)]
 pub struct ThreadData<'a> {
+    /// The network for accumulators and evaluation, kept until `update_network`
+    pub network: NetworkRef,
     pub search_history: Vec<u64>,
-    pub accumulators: [Accumulator; MAX_DEPTH as usize],
+    pub accumulators: [AccumulatorEntry; MAX_DEPTH as usize],
+    pub finny_table: FinnyTable,
     pub evals: [i32; MAX_DEPTH as usize],
     pub pv: PrincipalVariation,
     pub root_score: i32,
+    pub multi_pvs: Vec<(i32, PrincipalVariation)>,
+    pub excluded_root_moves: Vec<Move>,
+    pub search_moves: Vec<Move>,
     pub depth_reached: i32,
     pub tt: TTSlice<'a>,
     pub history: HistoryTable,
+    pub capture_history: CaptureHistory,
+    /// Quiet histories following the moves 1 and 2 plies earlier
+    pub cont_history: [ContinuationHistory; CONT_HIST_PLIES],
+    /// Static eval corrections keyed by the pawn structure
+    pub pawn_correction: CorrectionHistory,
+    /// The piece moved and its destination at each ply of the current line, `None` for null moves
+    pub move_stack: [Option<(Piece, Square)>; MAX_DEPTH as usize],
+    /// Two quiet moves per ply that recently caused a beta cutoff
+    pub killers: [[Move; 2]; MAX_DEPTH as usize],
+    /// The quiet move that last refuted each previous move, indexed by its piece and destination
+    pub counter_moves: [[Move; 64]; 6],
+    /// The TT move left out of a singular extension search at each ply, null if there is none
+    pub excluded_moves: [Move; MAX_DEPTH as usize],
+    /// How many extensions of more than one ply have been made along the current line
+    pub double_extensions: [i32; MAX_DEPTH as usize],
     pub nmp_enabled: bool,
 }
 
 impl<'a> ThreadData<'a> {
     pub fn new(board: &Board, tt: TTSlice<'a>) -> ThreadData<'a> {
+        let network = network();
         let mut data = Self {
             search_history: vec![],
-            accumulators: [Accumulator::default(); MAX_DEPTH as usize],
+            accumulators: [AccumulatorEntry::new(&network); MAX_DEPTH as usize],
+            finny_table: FinnyTable::new(&network),
+            network,
             evals: [0; MAX_DEPTH as usize],
             pv: PrincipalVariation::new(),
             root_score: 0,
+            multi_pvs: vec![],
+            excluded_root_moves: vec![],
+            search_moves: vec![],
             depth_reached: 0,
             tt,
             history: HistoryTable::new(),
+            capture_history: CaptureHistory::new(),
+            cont_history: Default::default(),
+            pawn_correction: CorrectionHistory::new(),
+            move_stack: [None; MAX_DEPTH as usize],
+            killers: [[Move::NULL; 2]; MAX_DEPTH as usize],
+            counter_moves: [[Move::NULL; 64]; 6],
+            excluded_moves: [Move::NULL; MAX_DEPTH as usize],
+            double_extensions: [0; MAX_DEPTH as usize],
             nmp_enabled: true,
         };
 
-        board.refresh_accumulator(&mut data.accumulators[0]);
+        board.refresh_root_accumulator(&mut data);
 
         data
     }
 
+    /// Switch to the active network, refreshing the root accumulator for `board`
+    pub fn update_network(&mut self, board: &Board) {
+        self.network = network();
+        self.finny_table = FinnyTable::new(&self.network);
+        board.refresh_root_accumulator(self);
+    }
+
+    /// The move made `plies_back` plies before `ply`, if it exists and isn't a null move
+    fn prev_move(&self, ply: usize, plies_back: usize) -> Option<(Piece, Square)> {
+        self.move_stack[ply.checked_sub(plies_back)?]
+    }
+
+    /// Combined butterfly and continuation history score of a quiet move at `ply`
+    pub fn quiet_history(&self, ply: usize, piece: Piece, to: Square) -> i32 {
+        let mut score = self.history.get(piece, to) as i32;
+        for (i, cont_history) in self.cont_history.iter().enumerate() {
+            if let Some(prev) = self.prev_move(ply, i + 1) {
+                score += cont_history.get(prev).get(piece, to) as i32;
+            }
+        }
+        score
+    }
+
     pub fn update_quiet_histories(
         &mut self,
         board: &Board,
+        ply: usize,
         depth: i32,
         bonus_quiet: Move,
         quiets_tried: &MoveList,
     ) {
         let delta = self.history.delta(depth);
-        // bonus for best quiet
-        apply_history_bonus(
-            self.history.get_mut(
-                board.piece_on(bonus_quiet.from()).unwrap(),
-                bonus_quiet.to(),
-            ),
-            delta,
-        );
-
-        // malus for quiets that weren't the best
-        for &malus_quiet in quiets_tried.iter_moves() {
-            // don't punish the best move!
-            if malus_quiet == bonus_quiet {
-                continue;
+        let prev_moves: [_; CONT_HIST_PLIES] = std::array::from_fn(|i| self.prev_move(ply, i + 1));
+
+        for &quiet in quiets_tried.iter_moves() {
+            // bonus for best quiet, malus for quiets that weren't the best
+            let update = if quiet == bonus_quiet {
+                apply_history_bonus
+            } else {
+                apply_history_malus
+            };
+            let piece = board.piece_on(quiet.from()).unwrap();
+
+            update(self.history.get_mut(piece, quiet.to()), delta);
+            for (cont_history, prev) in self.cont_history.iter_mut().zip(prev_moves) {
+                if let Some(prev) = prev {
+                    update(cont_history.get_mut(prev).get_mut(piece, quiet.to()), delta);
+                }
             }
-            apply_history_malus(
-                self.history.get_mut(
-                    board.piece_on(malus_quiet.from()).unwrap(),
-                    malus_quiet.to(),
-                ),
+        }
+    }
+
+    /// Adjust a static eval by what earlier searches of similar positions found, keeping it out of
+    /// the range of TB wins and mates
+    pub fn correct_eval(&self, board: &Board, eval: i32) -> i32 {
+        let correction = self.pawn_correction.get(board.player(), board.pawn_hash());
+        (eval + correction).clamp(-MIN_TB_WIN_SCORE + 1, MIN_TB_WIN_SCORE - 1)
+    }
+
+    /// Learn the difference between a search score and the uncorrected static eval
+    pub fn update_correction_history(&mut self, board: &Board, depth: i32, diff: i32) {
+        self.pawn_correction
+            .update(board.player(), board.pawn_hash(), depth, diff);
+    }
+
+    /// Bonus for a capture that caused a beta cutoff, malus for the other captures tried before it
+    pub fn update_capture_histories(
+        &mut self,
+        board: &Board,
+        depth: i32,
+        best_move: Move,
+        captures_tried: &MoveList,
+    ) {
+        let delta = self.history.delta(depth);
+
+        for &capture in captures_tried.iter_moves() {
+            let update = if capture == best_move {
+                apply_history_bonus
+            } else {
+                apply_history_malus
+            };
+            let piece = board.piece_on(capture.from()).unwrap();
+            // en passant captures have an empty destination
+            let captured = board.piece_on(capture.to()).unwrap_or(Pawn);
+
+            update(
+                self.capture_history.get_mut(piece, capture.to(), captured),
                 delta,
             );
         }
     }
 
+    /// The counter move to the move made on the previous ply, if there is one
+    pub fn counter_move(&self, ply: usize) -> Move {
+        self.prev_move(ply, 1)
+            .map_or(Move::NULL, |(piece, to)| self.counter_moves[piece][to])
+    }
+
+    /// Remember a quiet move that caused a beta cutoff as a killer and as a counter move
+    pub fn update_refutations(&mut self, ply: usize, mv: Move) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != mv {
+            killers[1] = killers[0];
+            killers[0] = mv;
+        }
+
+        if let Some((piece, to)) = self.prev_move(ply, 1) {
+            self.counter_moves[piece][to] = mv;
+        }
+    }
+
+    /// Whether a root move is left out by UCI searchmoves or already found for an earlier MultiPV line
+    pub fn skip_root_move(&self, mv: Move) -> bool {
+        self.excluded_root_moves.contains(&mv)
+            || (!self.search_moves.is_empty() && !self.search_moves.contains(&mv))
+    }
+
+    /// Whether the root search can miss the best move
+    pub fn root_moves_restricted(&self) -> bool {
+        !self.excluded_root_moves.is_empty() || !self.search_moves.is_empty()
+    }
+
+    /// Prepare for a new search. Continuation and capture history, counter moves and pawn correction
+    /// history carry over from earlier searches, they are only cleared when the thread data is
+    /// rebuilt for a new game
     pub fn reset(&mut self) {
         self.pv.clear();
+        self.multi_pvs.clear();
+        self.excluded_root_moves.clear();
         self.history.clear();
+        self.killers = [[Move::NULL; 2]; MAX_DEPTH as usize];
+        self.excluded_moves = [Move::NULL; MAX_DEPTH as usize];
         self.nmp_enabled = true;
     }
 }
@@ -1,5 +1,7 @@
 use std::time::{Duration, Instant};
 
+use crate::search::MATE_SCORE;
+
 #[derive(Copy, Clone, Debug)]
 pub struct TimeOptions {
     /// the maximum percent of the total remaining time to use
@@ -27,6 +29,8 @@ pub struct TimeManager {
     soft_time: Option<Duration>,
     depth_limit: Option<usize>,
     node_limit: Option<u64>,
+    mate_limit: Option<usize>,
+    ponder: bool,
 }
 
 impl TimeManager {
@@ -38,6 +42,8 @@ impl TimeManager {
             soft_time: None,
             depth_limit: None,
             node_limit: None,
+            mate_limit: None,
+            ponder: false,
         }
     }
 
@@ -101,6 +107,28 @@ impl TimeManager {
         self
     }
 
+    /// also stop once a mate in this many moves or fewer is found
+    pub fn fixed_mate(mut self, moves: Option<usize>) -> Self {
+        self.mate_limit = moves;
+
+        self
+    }
+
+    /// ignore time limits until ponderhit, they still apply from the start of the search afterwards
+    pub fn ponder(mut self, ponder: bool) -> Self {
+        self.ponder = ponder;
+
+        self
+    }
+
+    pub fn ponderhit(&mut self) {
+        self.ponder = false;
+    }
+
+    pub fn pondering(&self) -> bool {
+        self.ponder
+    }
+
     pub fn elapsed(&self) -> Duration {
         self.start_time.elapsed()
     }
@@ -121,7 +149,19 @@ impl TimeManager {
         }
     }
 
+    pub fn mate_limit_reached(&self, score: i32) -> bool {
+        if let Some(limit) = self.mate_limit {
+            // mate in n moves is 2n - 1 plies
+            (MATE_SCORE - score + 1) / 2 <= limit as i32
+        } else {
+            false
+        }
+    }
+
     pub fn soft_time_limit_reached(&self) -> bool {
+        if self.ponder {
+            return false;
+        }
         if let Some(limit) = self.soft_time {
             self.elapsed() >= limit
         } else {
@@ -130,6 +170,9 @@ impl TimeManager {
     }
 
     pub fn hard_time_limit_reached(&self) -> bool {
+        if self.ponder {
+            return false;
+        }
         if let Some(limit) = self.hard_time {
             self.elapsed() >= limit
         } else {
@@ -104,7 +104,7 @@ impl TT {
         }
     }
 
-    pub fn slice(&self) -> TTSlice {
+    pub fn slice(&self) -> TTSlice<'_> {
         TTSlice {
             entries: &self.entries[..],
         }
@@ -46,9 +46,8 @@ fn generate_lmr_reductions() -> [[i8; 64]; 64] {
     let mut reductions = [[0; 64]; 64];
 
     (1..64).for_each(|depth| {
-        for played in 1..64 {
-            reductions[depth][played] =
-                (1.2232 + (depth as f32).ln() * (played as f32).ln() / 1.9965) as i8;
+        for (played, r) in reductions[depth].iter_mut().enumerate().skip(1) {
+            *r = (1.2232 + (depth as f32).ln() * (played as f32).ln() / 1.9965) as i8;
         }
     });
     reductions
@@ -0,0 +1,90 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use crinnge_lib::{
+    data::{parse_line, ChessBoard},
+    nnue::{feature::Feature, output_bucket, INPUT_BUCKETS, INPUT_SIZE},
+    types::*,
+};
+
+// feature indices are stored as u16
+const _: () = assert!(INPUT_SIZE * INPUT_BUCKETS <= u16::MAX as usize + 1);
+
+/// A position ready for training, from the side to move's point of view
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    features: [u16; 32],
+    count: u8,
+    bucket: u8,
+    pub score: i16,
+    pub result: f32,
+}
+
+impl Entry {
+    pub fn new(board: &ChessBoard) -> Self {
+        let [king, _] = board.king_squares();
+        let mut features = [0; 32];
+        let mut count = 0;
+        // the side to move is white in a ChessBoard, so its accumulator uses white's layout
+        for (color, piece, square) in board.pieces() {
+            let feature = Feature {
+                color,
+                piece,
+                square,
+            };
+            features[count] = feature.index(White, king) as u16;
+            count += 1;
+        }
+
+        Self {
+            features,
+            count: count as u8,
+            bucket: output_bucket(count as u32) as u8,
+            score: board.score(),
+            result: board.result(),
+        }
+    }
+
+    pub fn features(&self) -> &[u16] {
+        &self.features[..self.count as usize]
+    }
+
+    pub fn bucket(&self) -> usize {
+        self.bucket as usize
+    }
+}
+
+/// Load training positions from a file of `fen | score | result` lines if it ends in `.txt`,
+/// otherwise from packed `ChessBoard` records
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Entry>> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut entries = vec![];
+
+    if path.extension().is_some_and(|ext| ext == "txt") {
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let board = parse_line(&line)
+                .and_then(|(board, score, result)| ChessBoard::from_board(&board, score, result))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid training data on line {}", i + 1),
+                    )
+                })?;
+            entries.push(Entry::new(&board));
+        }
+    } else {
+        while let Some(board) = ChessBoard::read_from(&mut reader)? {
+            entries.push(Entry::new(&board));
+        }
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,195 @@
+mod dataset;
+mod network;
+
+use std::{env, error::Error, fs, path::PathBuf, thread, time::Instant};
+
+use crinnge_lib::{board::Board, data::ChessBoard, nnue::NetworkHeader, rng::Rng};
+use dataset::Entry;
+use network::{load_checkpoint, save_checkpoint, AdamW, FloatNetwork, PARAMS};
+
+const USAGE: &str = "usage: crinnge_train data FILE [data FILE ...] [out DIR] [name NAME] \
+                     [epochs N] [batch N] [lr F] [lr_drop N] [lr_gamma F] [wdl F] [decay F] \
+                     [threads N] [save_rate N] [seed N] [resume FILE]";
+
+#[derive(Clone, Debug)]
+struct TrainOptions {
+    /// Text files ending in `.txt`, or packed ChessBoard records
+    data: Vec<String>,
+    out: PathBuf,
+    name: String,
+    epochs: usize,
+    batch: usize,
+    lr: f32,
+    /// Multiply the learning rate by `lr_gamma` every `lr_drop` epochs
+    lr_drop: usize,
+    lr_gamma: f32,
+    /// Weight of the game result in the target, the rest is the search score
+    wdl: f32,
+    decay: f32,
+    threads: usize,
+    /// Save a checkpoint every `save_rate` epochs, and always after the last
+    save_rate: usize,
+    seed: u64,
+    resume: Option<String>,
+}
+
+impl TrainOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            data: vec![],
+            out: PathBuf::from("checkpoints"),
+            name: String::from("crinnge"),
+            epochs: 30,
+            batch: 16384,
+            lr: 0.001,
+            lr_drop: 10,
+            lr_gamma: 0.3,
+            wdl: 0.25,
+            decay: 0.01,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            save_rate: 10,
+            seed: 0,
+            resume: None,
+        };
+
+        while let Some(name) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {name}\n{USAGE}"))?;
+            let invalid = || format!("Invalid value for {name}: {value}");
+            let int = || value.parse::<usize>().map_err(|_| invalid());
+            let float = || value.parse::<f32>().map_err(|_| invalid());
+            match name.as_str() {
+                "data" => options.data.push(value),
+                "out" => options.out = PathBuf::from(value),
+                "name" => options.name = value,
+                "epochs" => options.epochs = int()?,
+                "batch" => options.batch = int()?.max(1),
+                "lr" => options.lr = float()?,
+                "lr_drop" => options.lr_drop = int()?.max(1),
+                "lr_gamma" => options.lr_gamma = float()?,
+                "wdl" => options.wdl = float()?.clamp(0.0, 1.0),
+                "decay" => options.decay = float()?,
+                "threads" => options.threads = int()?.max(1),
+                "save_rate" => options.save_rate = int()?.max(1),
+                "seed" => options.seed = int()? as u64,
+                "resume" => options.resume = Some(value),
+                _ => return Err(format!("Unknown option {name}\n{USAGE}")),
+            }
+        }
+
+        if options.data.is_empty() {
+            return Err(format!("No training data given\n{USAGE}"));
+        }
+
+        Ok(options)
+    }
+
+    fn lr(&self, epoch: usize) -> f32 {
+        self.lr * self.lr_gamma.powi(((epoch - 1) / self.lr_drop) as i32)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = TrainOptions::parse(env::args().skip(1))?;
+
+    let mut entries = vec![];
+    for path in options.data.iter() {
+        let loaded = dataset::load(path).map_err(|e| format!("{path}: {e}"))?;
+        println!("Loaded {} positions from {path}", loaded.len());
+        entries.extend(loaded);
+    }
+    if entries.is_empty() {
+        return Err("No positions in the training data".into());
+    }
+
+    let mut rng = Rng::new(options.seed);
+    let mut optimiser = AdamW::new(options.decay);
+    let mut net = match &options.resume {
+        Some(path) => load_checkpoint(path, &mut optimiser)?,
+        None => FloatNetwork::new(&mut rng),
+    };
+    fs::create_dir_all(&options.out)?;
+
+    println!(
+        "Training {} on {} positions for {} epochs",
+        NetworkHeader::of(&net.quantise(), 0),
+        entries.len(),
+        options.epochs
+    );
+
+    // the start position eval is a quick sanity check on training progress
+    let startpos = Entry::new(&ChessBoard::from_board(&Board::new(), 0, 0.5).unwrap());
+
+    for epoch in 1..=options.epochs {
+        let start = Instant::now();
+        let lr = options.lr(epoch);
+        shuffle(&mut entries, &mut rng);
+
+        let mut loss = 0.0;
+        for batch in entries.chunks(options.batch) {
+            let (grad, batch_loss) = gradient(&net, batch, options.wdl, options.threads);
+            optimiser.step(&mut net.params, &grad, lr);
+            loss += batch_loss;
+        }
+
+        let secs = start.elapsed().as_secs_f64().max(0.001);
+        println!(
+            "epoch {epoch}: loss {:.6}, lr {lr:.6}, startpos {:.0}, {:.0} positions/s",
+            loss / entries.len() as f64,
+            net.evaluate(&startpos),
+            entries.len() as f64 / secs
+        );
+
+        if epoch % options.save_rate == 0 || epoch == options.epochs {
+            let path = options.out.join(format!("{}-{epoch}", options.name));
+            save_checkpoint(path.with_extension("ckpt"), &net, &optimiser)?;
+            net.quantise().save(path.with_extension("bin"))?;
+            println!("Saved {}", path.with_extension("bin").display());
+        }
+    }
+
+    Ok(())
+}
+
+fn shuffle(entries: &mut [Entry], rng: &mut Rng) {
+    for i in (1..entries.len()).rev() {
+        entries.swap(i, rng.below(i + 1));
+    }
+}
+
+/// Mean gradient and total loss of a batch, split across threads
+fn gradient(net: &FloatNetwork, batch: &[Entry], wdl: f32, threads: usize) -> (Vec<f32>, f64) {
+    let chunk_size = batch.len().div_ceil(threads);
+    let results: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = batch
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let mut grad = vec![0.0; PARAMS];
+                    let loss: f64 = chunk
+                        .iter()
+                        .map(|entry| f64::from(net.backprop(entry, wdl, &mut grad)))
+                        .sum();
+                    (grad, loss)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut total = vec![0.0; PARAMS];
+    let mut loss = 0.0;
+    for (grad, chunk_loss) in results {
+        for (t, g) in total.iter_mut().zip(grad) {
+            *t += g;
+        }
+        loss += chunk_loss;
+    }
+    let scale = 1.0 / batch.len() as f32;
+    for t in total.iter_mut() {
+        *t *= scale;
+    }
+
+    (total, loss)
+}
@@ -0,0 +1,366 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crinnge_lib::{
+    nnue::{
+        layers::Activation, Network, EVAL_SCALE, HIDDEN_SIZE, INPUT_BUCKETS, INPUT_SIZE,
+        OUTPUT_BUCKETS, QA, QB,
+    },
+    rng::Rng,
+};
+
+use crate::dataset::Entry;
+
+// the trainer only knows the feature transformer and output layer
+const _: () = assert!(!<Network>::HAS_LAYERS);
+
+const FT_INPUTS: usize = INPUT_SIZE * INPUT_BUCKETS;
+
+/// Offsets of each parameter group in the flat parameter vector, laid out like `Network`
+pub const FT_WEIGHTS: usize = 0;
+pub const FT_BIAS: usize = FT_WEIGHTS + FT_INPUTS * HIDDEN_SIZE;
+pub const OUT_WEIGHTS: usize = FT_BIAS + HIDDEN_SIZE;
+pub const OUT_BIAS: usize = OUT_WEIGHTS + OUTPUT_BUCKETS * HIDDEN_SIZE;
+pub const PARAMS: usize = OUT_BIAS + OUTPUT_BUCKETS;
+
+/// Parameters are clipped to this so the quantised accumulators can't overflow
+pub const MAX_WEIGHT: f32 = 1.98;
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"CRTC";
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Float version of the network, where 1.0 is `QA` in the feature transformer and `QB` in the
+/// output weights, and an output of 1.0 is `EVAL_SCALE` centipawns
+#[derive(Clone, Debug)]
+pub struct FloatNetwork {
+    pub params: Vec<f32>,
+}
+
+impl FloatNetwork {
+    pub fn new(rng: &mut Rng) -> Self {
+        let mut uniform = |limit: f32| {
+            let unit = (rng.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+            (2.0 * unit - 1.0) * limit
+        };
+
+        let mut params = vec![0.0; PARAMS];
+        // about 32 features are active at once
+        for w in params[FT_WEIGHTS..FT_BIAS].iter_mut() {
+            *w = uniform(1.0 / 32f32.sqrt());
+        }
+        for w in params[OUT_WEIGHTS..OUT_BIAS].iter_mut() {
+            *w = uniform(1.0 / (HIDDEN_SIZE as f32).sqrt());
+        }
+
+        Self { params }
+    }
+
+    fn hidden(&self, entry: &Entry) -> [f32; HIDDEN_SIZE] {
+        let mut hidden = [0.0; HIDDEN_SIZE];
+        hidden.copy_from_slice(&self.params[FT_BIAS..OUT_WEIGHTS]);
+        for &feature in entry.features() {
+            let offset = FT_WEIGHTS + feature as usize * HIDDEN_SIZE;
+            for (h, &w) in hidden
+                .iter_mut()
+                .zip(&self.params[offset..offset + HIDDEN_SIZE])
+            {
+                *h += w;
+            }
+        }
+        hidden
+    }
+
+    fn output(&self, hidden: &[f32; HIDDEN_SIZE], bucket: usize) -> f32 {
+        let weights = &self.params[OUT_WEIGHTS + bucket * HIDDEN_SIZE..][..HIDDEN_SIZE];
+        hidden
+            .iter()
+            .zip(weights)
+            .map(|(&h, &w)| h.clamp(0.0, 1.0) * w)
+            .sum::<f32>()
+            + self.params[OUT_BIAS + bucket]
+    }
+
+    /// Evaluation in centipawns for the side to move
+    pub fn evaluate(&self, entry: &Entry) -> f32 {
+        self.output(&self.hidden(entry), entry.bucket()) * EVAL_SCALE as f32
+    }
+
+    /// Add the gradient of the loss for `entry` to `grad` and return the loss. The target blends
+    /// the game result with the search score by `wdl`
+    pub fn backprop(&self, entry: &Entry, wdl: f32, grad: &mut [f32]) -> f32 {
+        let bucket = entry.bucket();
+        let hidden = self.hidden(entry);
+        let prediction = sigmoid(self.output(&hidden, bucket));
+        let target =
+            wdl * entry.result + (1.0 - wdl) * sigmoid(f32::from(entry.score) / EVAL_SCALE as f32);
+
+        let error = prediction - target;
+        let g = 2.0 * error * prediction * (1.0 - prediction);
+
+        grad[OUT_BIAS + bucket] += g;
+        let out_weights = OUT_WEIGHTS + bucket * HIDDEN_SIZE;
+        let mut hidden_grad = [0.0; HIDDEN_SIZE];
+        for (j, &h) in hidden.iter().enumerate() {
+            grad[out_weights + j] += g * h.clamp(0.0, 1.0);
+            if h > 0.0 && h < 1.0 {
+                hidden_grad[j] = g * self.params[out_weights + j];
+            }
+        }
+
+        for (b, &hg) in grad[FT_BIAS..OUT_WEIGHTS].iter_mut().zip(&hidden_grad) {
+            *b += hg;
+        }
+        for &feature in entry.features() {
+            let offset = FT_WEIGHTS + feature as usize * HIDDEN_SIZE;
+            for (w, &hg) in grad[offset..offset + HIDDEN_SIZE]
+                .iter_mut()
+                .zip(&hidden_grad)
+            {
+                *w += hg;
+            }
+        }
+
+        error * error
+    }
+
+    /// Quantise into the engine's network layout
+    pub fn quantise(&self) -> Box<Network> {
+        let quantise = |p: f32, scale: i32| {
+            (p * scale as f32)
+                .round()
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        };
+
+        let mut net = Network::zeroed();
+        for (f, weights) in net.feature_weights.iter_mut().enumerate() {
+            let offset = FT_WEIGHTS + f * HIDDEN_SIZE;
+            for (q, &p) in weights
+                .iter_mut()
+                .zip(&self.params[offset..offset + HIDDEN_SIZE])
+            {
+                *q = quantise(p, QA);
+            }
+        }
+        for (q, &p) in net
+            .feature_bias
+            .iter_mut()
+            .zip(&self.params[FT_BIAS..OUT_WEIGHTS])
+        {
+            *q = quantise(p, QA);
+        }
+        for (b, weights) in net.output_weights.iter_mut().enumerate() {
+            let offset = OUT_WEIGHTS + b * HIDDEN_SIZE;
+            for (q, &p) in weights
+                .iter_mut()
+                .zip(&self.params[offset..offset + HIDDEN_SIZE])
+            {
+                *q = quantise(p, QB);
+            }
+        }
+        for (q, &p) in net
+            .output_bias
+            .iter_mut()
+            .zip(&self.params[OUT_BIAS..PARAMS])
+        {
+            *q = quantise(p, QA * QB);
+        }
+        net.ft_activation = Activation::CReLU;
+
+        net
+    }
+}
+
+/// AdamW, clipping parameters to `MAX_WEIGHT` after each step
+#[derive(Clone, Debug)]
+pub struct AdamW {
+    pub momentum: Vec<f32>,
+    pub velocity: Vec<f32>,
+    pub decay: f32,
+    /// Steps taken so far, for bias correction of the zero-initialised moments
+    pub steps: u64,
+}
+
+impl AdamW {
+    const BETA1: f32 = 0.9;
+    const BETA2: f32 = 0.999;
+    const EPSILON: f32 = 1e-8;
+
+    pub fn new(decay: f32) -> Self {
+        Self {
+            momentum: vec![0.0; PARAMS],
+            velocity: vec![0.0; PARAMS],
+            decay,
+            steps: 0,
+        }
+    }
+
+    pub fn step(&mut self, params: &mut [f32], grad: &[f32], lr: f32) {
+        self.steps += 1;
+        let momentum_correction = 1.0 - Self::BETA1.powf(self.steps as f32);
+        let velocity_correction = 1.0 - Self::BETA2.powf(self.steps as f32);
+
+        for (((p, &g), m), v) in params
+            .iter_mut()
+            .zip(grad)
+            .zip(self.momentum.iter_mut())
+            .zip(self.velocity.iter_mut())
+        {
+            *m = Self::BETA1 * *m + (1.0 - Self::BETA1) * g;
+            *v = Self::BETA2 * *v + (1.0 - Self::BETA2) * g * g;
+            let m_hat = *m / momentum_correction;
+            let v_hat = *v / velocity_correction;
+            *p -= lr * (m_hat / (v_hat.sqrt() + Self::EPSILON) + self.decay * *p);
+            *p = p.clamp(-MAX_WEIGHT, MAX_WEIGHT);
+        }
+    }
+}
+
+/// Save the float parameters and optimiser state so training can be resumed
+pub fn save_checkpoint<P: AsRef<Path>>(
+    path: P,
+    net: &FloatNetwork,
+    optimiser: &AdamW,
+) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(20 + 12 * PARAMS);
+    bytes.extend_from_slice(CHECKPOINT_MAGIC);
+    bytes.extend_from_slice(&(PARAMS as u64).to_le_bytes());
+    bytes.extend_from_slice(&optimiser.steps.to_le_bytes());
+    for values in [&net.params, &optimiser.momentum, &optimiser.velocity] {
+        for v in values.iter() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    fs::write(path, bytes)
+}
+
+pub fn load_checkpoint<P: AsRef<Path>>(path: P, optimiser: &mut AdamW) -> io::Result<FloatNetwork> {
+    let bytes = fs::read(path)?;
+    let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+
+    if bytes.len() < 20 || &bytes[0..4] != CHECKPOINT_MAGIC {
+        return Err(invalid("not a crinnge_train checkpoint"));
+    }
+    let params = u64::from_le_bytes(bytes[4..12].try_into().unwrap()) as usize;
+    if params != PARAMS || bytes.len() != 20 + 12 * PARAMS {
+        return Err(invalid(
+            "checkpoint is for a different network architecture",
+        ));
+    }
+
+    optimiser.steps = u64::from_le_bytes(bytes[12..20].try_into().unwrap());
+    let mut values = bytes[20..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()));
+    let net = FloatNetwork {
+        params: values.by_ref().take(PARAMS).collect(),
+    };
+    optimiser.momentum = values.by_ref().take(PARAMS).collect();
+    optimiser.velocity = values.collect();
+
+    Ok(net)
+}
+
+#[cfg(test)]
+mod tests {
+    use crinnge_lib::{board::Board, data::ChessBoard, rng::Rng};
+
+    use super::{AdamW, FloatNetwork, PARAMS};
+    use crate::dataset::Entry;
+
+    const FENS: [&str; 4] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+    ];
+
+    fn entry(fen: &str) -> Entry {
+        let board = Board::from_fen(fen).unwrap();
+        Entry::new(&ChessBoard::from_board(&board, 50, 1.0).unwrap())
+    }
+
+    #[test]
+    fn test_gradient_matches_finite_differences() {
+        let mut rng = Rng::new(1);
+        let net = FloatNetwork::new(&mut rng);
+
+        for fen in FENS {
+            let entry = entry(fen);
+            let mut grad = vec![0.0; PARAMS];
+            net.backprop(&entry, 0.5, &mut grad);
+
+            // check the parameters with the largest gradients
+            let mut indices: Vec<_> = (0..PARAMS).collect();
+            indices.sort_by(|&a, &b| grad[b].abs().total_cmp(&grad[a].abs()));
+            for &i in indices.iter().take(20) {
+                let nudged = |delta: f32| {
+                    let mut net = net.clone();
+                    net.params[i] += delta;
+                    net.backprop(&entry, 0.5, &mut vec![0.0; PARAMS])
+                };
+                let h = 1e-3;
+                let numeric = (nudged(h) - nudged(-h)) / (2.0 * h);
+                assert!(
+                    (numeric - grad[i]).abs() <= 1e-3 + 0.05 * grad[i].abs(),
+                    "{fen}: parameter {i} gradient {} numeric {numeric}",
+                    grad[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantised_network_matches_float() {
+        let mut rng = Rng::new(2);
+        let net = FloatNetwork::new(&mut rng);
+        let quantised_net = net.quantise();
+
+        for fen in FENS {
+            let entry = entry(fen);
+            let mut acc = quantised_net.feature_bias;
+            for &feature in entry.features() {
+                let weights = &quantised_net.feature_weights[feature as usize];
+                for (a, &w) in acc.iter_mut().zip(weights.iter()) {
+                    *a += w;
+                }
+            }
+            let quantised = quantised_net.evaluate(&acc, entry.bucket());
+            let float = net.evaluate(&entry);
+            assert!(
+                (quantised as f32 - float).abs() < 10.0,
+                "{fen}: quantised {quantised} float {float}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_adamw_bias_correction() {
+        // with bias correction the first step moves every parameter by the learning rate,
+        // whatever the gradient's scale
+        let mut optimiser = AdamW::new(0.0);
+        let mut params = vec![0.0; 4];
+        let grad = [1e-4, -0.5, 3.0, -200.0];
+        optimiser.step(&mut params, &grad, 0.01);
+        for (p, g) in params.iter().zip(grad) {
+            assert!(
+                (p + 0.01 * g.signum()).abs() < 1e-5,
+                "gradient {g} step {p}"
+            );
+        }
+
+        // minimise (x - 0.5)^2 from 0 with a constant gradient scale
+        let mut optimiser = AdamW::new(0.0);
+        let mut x = [0.0];
+        for _ in 0..500 {
+            let grad = [2.0 * (x[0] - 0.5)];
+            optimiser.step(&mut x, &grad, 0.01);
+        }
+        assert!((x[0] - 0.5).abs() < 0.01, "converged to {}", x[0]);
+    }
+}